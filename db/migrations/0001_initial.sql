@@ -1,9 +1,12 @@
-create table locations
+-- The schema as it was before migrations were tracked. Databases created back then already have
+-- these tables, so everything here must be safe to run again.
+
+create table if not exists locations
 (
     id serial primary key
 );
 
-create table users
+create table if not exists users
 (
     id          serial primary key,
     fingerprint bytea not null unique,
@@ -19,7 +22,9 @@ create table users
 );
 
 insert into locations (id)
-values (0);
+values (0)
+on conflict do nothing;
 
 insert into locations (id)
-values (1);
\ No newline at end of file
+values (1)
+on conflict do nothing;
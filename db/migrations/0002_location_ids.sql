@@ -0,0 +1,14 @@
+-- Locations are defined in content files now, and referred to by the id given to them there.
+-- Dropping location_id also drops its foreign key and check constraint.
+
+alter table users
+    add column location text;
+
+update users
+set location = case location_id when 1 then 'bastow-woodlands' else 'bastow' end;
+
+alter table users
+    alter column location set not null,
+    drop column location_id;
+
+drop table locations;
@@ -0,0 +1,11 @@
+-- A fight in progress. A character fights at most one monster at a time.
+
+create table encounters
+(
+    user_id        int  primary key references users on delete cascade,
+    monster        text not null,
+    monster_health int  not null
+        check (monster_health > 0),
+    round          int  not null default 0
+        check (round >= 0)
+);
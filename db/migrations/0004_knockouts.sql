@@ -0,0 +1,17 @@
+-- Knocked out characters wake up at the last inn they visited. Characters that have not visited
+-- one yet wake up at the start of the world.
+
+alter table users
+    add column last_inn text;
+
+-- Notable things that happened to a character, newest last.
+create table history
+(
+    id          serial primary key,
+    user_id     int         not null references users on delete cascade,
+    happened_at timestamptz not null default now(),
+    kind        text        not null,
+    description text        not null
+);
+
+create index history_user_id_idx on history (user_id);
@@ -0,0 +1,9 @@
+-- Characters gain experience by fighting, and go up a level as it grows. The level is stored
+-- rather than derived from the experience, so that changing the level curve never takes levels
+-- away from anyone.
+
+alter table users
+    add column level int not null default 1
+        check (level >= 1),
+    add column xp    int not null default 0
+        check (xp >= 0);
@@ -0,0 +1,21 @@
+-- Attributes are bought with points when a character is created. Characters created before
+-- attributes existed have none, which leaves their stats as they were.
+
+alter table users
+    add column strength int not null default 0
+        check (strength >= 0),
+    add column agility  int not null default 0
+        check (agility >= 0),
+    add column vitality int not null default 0
+        check (vitality >= 0);
+
+-- A character that is still being created, one step of the creation wizard at a time.
+create table character_drafts
+(
+    fingerprint bytea primary key
+        check (length(fingerprint) = 32),
+    name        text  not null,
+    strength    int   not null default 0,
+    agility     int   not null default 0,
+    vitality    int   not null default 0
+);
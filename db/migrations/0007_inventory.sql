@@ -0,0 +1,10 @@
+-- What each character carries. Items are defined in content files and referred to by their id.
+
+create table inventory
+(
+    user_id  int  not null references users on delete cascade,
+    item     text not null,
+    quantity int  not null
+        check (quantity > 0),
+    primary key (user_id, item)
+);
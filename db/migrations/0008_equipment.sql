@@ -0,0 +1,9 @@
+-- What each character wears, one item for each slot. Worn items are no longer in the inventory.
+
+create table equipment
+(
+    user_id int  not null references users on delete cascade,
+    slot    text not null,
+    item    text not null,
+    primary key (user_id, slot)
+);
@@ -0,0 +1,6 @@
+-- Gold is earned in fights and spent at inns. The check is what refuses to spend gold a
+-- character does not have.
+
+alter table users
+    add column gold int not null default 0
+        check (gold >= 0);
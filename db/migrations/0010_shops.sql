@@ -0,0 +1,12 @@
+-- What is left of the limited stock of shops, shared by all characters. Shops are defined in the
+-- content, a missing row means the shop has not sold any of the item yet.
+
+create table shop_stock
+(
+    shop         text        not null,
+    item         text        not null,
+    remaining    int         not null
+        check (remaining >= 0),
+    restocked_at timestamptz not null default now(),
+    primary key (shop, item)
+);
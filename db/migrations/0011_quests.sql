@@ -0,0 +1,12 @@
+-- Quests a character has taken on. Quests are defined in the content, progress holds a count for
+-- each of their objectives, in order.
+
+create table quests
+(
+    user_id      int         not null references users on delete cascade,
+    quest        text        not null,
+    progress     int[]       not null,
+    started_at   timestamptz not null default now(),
+    completed_at timestamptz,
+    primary key (user_id, quest)
+);
@@ -0,0 +1,10 @@
+-- Where a character is in a conversation with a non-player character, so that it can be picked up
+-- again later. Characters and their dialogue are defined in the content. A character talks to
+-- one other character at a time.
+
+create table conversations
+(
+    user_id int  primary key references users on delete cascade,
+    npc     text not null,
+    node    text not null
+);
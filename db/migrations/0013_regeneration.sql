@@ -0,0 +1,5 @@
+-- Characters regenerate health over time. Rather than updating every character all the time, the
+-- health they regenerated is worked out whenever they are loaded, counting from this timestamp.
+
+alter table users
+    add column health_updated_at timestamptz not null default now();
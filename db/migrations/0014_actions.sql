@@ -0,0 +1,5 @@
+-- Every roll of the dice is derived from the character and how many actions they took, so that
+-- what happened can be replayed. This counts the actions.
+
+alter table users
+    add column actions bigint not null default 0;
@@ -0,0 +1,45 @@
+-- Accounts are who plays, and characters, still kept in users, what they play. An account is
+-- identified by its client certificate and owns any number of characters, one of which is active.
+-- Every certificate that had a character or a draft becomes an account of its own.
+
+create table accounts
+(
+    id          serial primary key,
+    fingerprint bytea       not null unique
+        check (length(fingerprint) = 32),
+    created_at  timestamptz not null default now()
+);
+
+insert into accounts (fingerprint)
+select fingerprint from users
+union
+select fingerprint from character_drafts;
+
+alter table users
+    add column account_id int references accounts on delete cascade;
+update users
+set account_id = accounts.id
+from accounts
+where accounts.fingerprint = users.fingerprint;
+alter table users
+    alter column account_id set not null,
+    drop column fingerprint;
+create index users_account_id_idx on users (account_id);
+
+alter table accounts
+    add column active_user_id int references users on delete set null;
+update accounts
+set active_user_id = users.id
+from users
+where users.account_id = accounts.id;
+
+-- An account works on one new character at a time.
+alter table character_drafts
+    add column account_id int references accounts on delete cascade;
+update character_drafts
+set account_id = accounts.id
+from accounts
+where accounts.fingerprint = character_drafts.fingerprint;
+alter table character_drafts
+    drop column fingerprint,
+    add primary key (account_id);
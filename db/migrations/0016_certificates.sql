@@ -0,0 +1,27 @@
+-- An account may be played with several client certificates, such as one on each device. Another
+-- certificate is linked to an account with a short-lived pairing code, made with one that already
+-- is.
+
+create table certificates
+(
+    fingerprint bytea primary key
+        check (length(fingerprint) = 32),
+    account_id  int         not null references accounts on delete cascade,
+    linked_at   timestamptz not null default now()
+);
+create index certificates_account_id_idx on certificates (account_id);
+
+insert into certificates (fingerprint, account_id, linked_at)
+select fingerprint, id, created_at
+from accounts;
+
+alter table accounts
+    drop column fingerprint;
+
+-- An account has one pairing code at a time.
+create table pairing_codes
+(
+    account_id int primary key references accounts on delete cascade,
+    code       text        not null unique,
+    expires_at timestamptz not null
+);
@@ -0,0 +1,15 @@
+-- A lost certificate need not mean a lost account: with its recovery secret, another certificate
+-- can claim it. Only a salted hash of the secret is kept, and attempts to enter it are recorded,
+-- so that they can be limited.
+
+alter table accounts
+    add column recovery_salt bytea,
+    add column recovery_hash bytea,
+    add check ((recovery_salt is null) = (recovery_hash is null));
+
+create table recovery_attempts
+(
+    account_id   int         not null references accounts on delete cascade,
+    attempted_at timestamptz not null default now()
+);
+create index recovery_attempts_account_id_idx on recovery_attempts (account_id, attempted_at);
@@ -0,0 +1,129 @@
+//! Accounts are played with client certificates, of which there may be several, such as one on
+//! each device. This holds what it takes to link another certificate to an account, or to recover
+//! one whose certificates are lost, while the accounts themselves are kept in the database.
+
+use std::time::{Duration, SystemTime};
+
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use rand::Rng;
+
+/// Characters of pairing codes, leaving out those easily mistaken for one another.
+const PAIRING_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const PAIRING_LENGTH: usize = 8;
+
+/// A new pairing code, in the form it is stored in. It must be hard to guess, so `rng` must not be
+/// the game's dice, which can be replayed.
+pub fn pairing_code(rng: &mut impl Rng) -> String {
+    (0..PAIRING_LENGTH)
+        .map(|_| PAIRING_ALPHABET[rng.gen_range(0..PAIRING_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// A pairing code the way it is shown, in two groups, such as `K7QD-M2XA`.
+pub fn show_pairing_code(code: &str) -> String {
+    let (first, second) = code.split_at(code.len() / 2);
+    format!("{}-{}", first, second)
+}
+
+/// A pairing code as entered by a player, in the form it is stored in. Case, dashes and spaces do
+/// not matter.
+pub fn parse_pairing_code(entered: &str) -> String {
+    entered.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// The shortest recovery secret accepted.
+pub const MIN_SECRET_LENGTH: usize = 8;
+/// Rounds of PBKDF2 a recovery secret goes through, to make each guess slow.
+const SECRET_ITERATIONS: usize = 100_000;
+
+/// A salted hash of a recovery secret, which lets another certificate claim an account.
+#[derive(Debug)]
+pub struct RecoverySecret {
+    pub salt: Vec<u8>,
+    pub hash: Vec<u8>,
+}
+
+impl RecoverySecret {
+    /// Hashes `secret` with a new, random salt.
+    pub fn new(secret: &str) -> Result<RecoverySecret, ErrorStack> {
+        let mut salt = vec![0; 16];
+        openssl::rand::rand_bytes(&mut salt)?;
+        let hash = hash_secret(secret, &salt)?;
+        Ok(RecoverySecret { salt, hash })
+    }
+
+    pub fn matches(&self, secret: &str) -> Result<bool, ErrorStack> {
+        let hash = hash_secret(secret, &self.salt)?;
+        Ok(hash.len() == self.hash.len() && openssl::memcmp::eq(&hash, &self.hash))
+    }
+}
+
+fn hash_secret(secret: &str, salt: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let mut hash = vec![0; 32];
+    openssl::pkcs5::pbkdf2_hmac(secret.as_bytes(), salt, SECRET_ITERATIONS, MessageDigest::sha256(), &mut hash)?;
+    Ok(hash)
+}
+
+/// How long until another attempt to recover an account may be made, given when the `attempts`
+/// in the last `window` were made, oldest first. `None` while fewer than `limit` were.
+pub fn retry_in(attempts: &[SystemTime], limit: usize, window: Duration, now: SystemTime) -> Option<Duration> {
+    let excess = attempts.len().checked_sub(limit)?;
+    let expires = attempts[excess] + window;
+    Some(expires.duration_since(now).unwrap_or_default())
+}
+
+/// A certificate fingerprint in hex, as it appears in paths.
+pub fn fingerprint_hex(fingerprint: &[u8]) -> String {
+    fingerprint.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The start of a certificate fingerprint in hex, enough to tell certificates apart.
+pub fn short_fingerprint(fingerprint: &[u8]) -> String {
+    fingerprint.iter().take(8).map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":")
+}
+
+pub fn parse_fingerprint(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+#[test]
+fn test_pairing_code() {
+    let code = pairing_code(&mut rand::thread_rng());
+    assert_eq!(code.len(), PAIRING_LENGTH);
+    assert_eq!(parse_pairing_code(&show_pairing_code(&code)), code);
+    assert_eq!(parse_pairing_code(" k7qd-m2xa "), "K7QDM2XA");
+}
+
+#[test]
+fn test_recovery_secret() {
+    let secret = RecoverySecret::new("correct horse").unwrap();
+    assert!(secret.matches("correct horse").unwrap());
+    assert!(!secret.matches("correct horse ").unwrap());
+    assert_ne!(RecoverySecret::new("correct horse").unwrap().salt, secret.salt);
+}
+
+#[test]
+fn test_retry_in() {
+    let minute = Duration::from_secs(60);
+    let start = SystemTime::UNIX_EPOCH;
+    let attempts = [start, start + minute, start + 2 * minute];
+    let now = start + 3 * minute;
+    assert_eq!(retry_in(&attempts, 4, 10 * minute, now), None);
+    assert_eq!(retry_in(&attempts, 3, 10 * minute, now), Some(7 * minute));
+    assert_eq!(retry_in(&attempts, 2, 10 * minute, now), Some(8 * minute));
+}
+
+#[test]
+fn test_parse_fingerprint() {
+    let fingerprint = (0..32).collect::<Vec<u8>>();
+    assert_eq!(parse_fingerprint(&fingerprint_hex(&fingerprint)), Some(fingerprint));
+    assert_eq!(parse_fingerprint("00"), None);
+    assert_eq!(parse_fingerprint(&"zz".repeat(32)), None);
+}
@@ -0,0 +1,255 @@
+//! Commands for operators, run from the command line instead of through the gemini server.
+
+use std::cmp::max;
+use std::{error, fs, io};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use structopt::StructOpt;
+use url::Url;
+
+use crate::certificate::{self, KeyType};
+use crate::client::{Client, Identity};
+use crate::config::Config;
+use crate::content::Content;
+use crate::progression;
+use crate::response::Status;
+use crate::storage::{self, Storage, User};
+use crate::world::World;
+
+#[derive(StructOpt, Debug)]
+pub enum UserCommand {
+    /// List all characters
+    List,
+    /// Show a single character
+    Show {
+        id: i32,
+    },
+    /// Change the name of a character
+    Rename {
+        id: i32,
+        name: String,
+    },
+    /// Delete a character for good
+    Delete {
+        id: i32,
+    },
+    /// Start the character over at level 1, with full health, back at the start
+    Reset {
+        id: i32,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct GenCertArgs {
+    /// Hostname, or IP address, the certificate is issued for
+    #[structopt(long, default_value = "localhost")]
+    hostname: String,
+
+    /// How many days the certificate stays valid
+    #[structopt(long, default_value = "3650")]
+    days: u32,
+
+    /// Type of the private key
+    #[structopt(long, default_value = "ecdsa-p256", possible_values = KeyType::NAMES)]
+    key_type: KeyType,
+
+    /// Where to write the certificate [default: transport.certificates_path, or namushul_certificate.pem]
+    #[structopt(long)]
+    certificate_path: Option<PathBuf>,
+
+    /// Where to write the private key [default: transport.private_key_path, or namushul_private_key.pem]
+    #[structopt(long)]
+    private_key_path: Option<PathBuf>,
+
+    /// Overwrite the files if they already exist
+    #[structopt(long)]
+    force: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct FetchArgs {
+    /// The gemini URL to request
+    url: Url,
+
+    /// Client certificate to present, encoded in PEM format
+    #[structopt(long, requires = "private-key-path")]
+    certificate_path: Option<PathBuf>,
+
+    /// Private key of the client certificate, encoded in PEM format
+    #[structopt(long, requires = "certificate-path")]
+    private_key_path: Option<PathBuf>,
+
+    /// How many redirects to follow before giving up
+    #[structopt(long, default_value = "5")]
+    max_redirects: usize,
+
+    /// Save the body to this file instead of printing it
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+type Result = std::result::Result<(), Box<dyn error::Error>>;
+
+fn location_name(world: &World, id: &str) -> String {
+    match world.location(id) {
+        Some(location) => location.name.clone(),
+        None => format!("Unknown location {}", id),
+    }
+}
+
+fn find_user(storage: &mut Storage, id: i32) -> std::result::Result<User, Box<dyn error::Error>> {
+    match storage.get_user_by_id(id) {
+        Err(storage::Error::NotFound) => Err(format!("There is no user #{}", id).into()),
+        result => Ok(result?),
+    }
+}
+
+fn print_user(world: &World, user: &User) {
+    println!("#{} {}", user.id, user.name);
+    println!("  Level: {} ({} XP)", user.level, user.xp);
+    let attributes = &user.attributes;
+    println!("  Strength {}, agility {}, vitality {}", attributes.strength, attributes.agility, attributes.vitality);
+    println!("  HP: {}/{}", user.health, user.max_health);
+    println!("  Gold: {}", user.gold);
+    println!("  Location: {}", location_name(world, &user.location));
+    if let Some(inn) = &user.last_inn {
+        println!("  Last inn: {}", location_name(world, inn));
+    }
+}
+
+pub fn migrate(config: &Config) -> Result {
+    let mut storage = Storage::new(&config.storage)?;
+    let applied = storage.migrate()?;
+    if applied.is_empty() {
+        println!("Database is up to date");
+    }
+    for migration in applied {
+        println!("Applied migration {} {}", migration.version, migration.name);
+    }
+    Ok(())
+}
+
+pub fn user(config: &Config, command: UserCommand) -> Result {
+    let content = Content::load(&config.game.content_path)?;
+    let world = &content.world;
+    let mut storage = Storage::new(&config.storage)?;
+    match command {
+        UserCommand::List => {
+            for user in storage.list_users()? {
+                println!("#{}\t{}\tLevel {}\tHP {}/{}\t{}", user.id, user.name, user.level, user.health, user.max_health, location_name(world, &user.location));
+            }
+        }
+        UserCommand::Show { id } => {
+            let user = find_user(&mut storage, id)?;
+            print_user(world, &user);
+            for (slot, item) in storage.get_equipment(&user)?.slots() {
+                println!("  Wears {} ({})", item, slot.segment());
+            }
+            for (item, quantity) in storage.get_inventory(&user)?.entries() {
+                println!("  Carries {} × {}", item, quantity);
+            }
+        }
+        UserCommand::Rename { id, name } => {
+            let user = find_user(&mut storage, id)?;
+            print_user(world, &storage.update_name(user, name)?);
+        }
+        UserCommand::Delete { id } => {
+            let user = find_user(&mut storage, id)?;
+            storage.delete_user(user.id)?;
+            println!("Deleted user #{}", id);
+        }
+        UserCommand::Reset { id } => {
+            let user = find_user(&mut storage, id)?;
+            // What the character wears is kept, along with its bonus.
+            let bonus = storage.get_equipment(&user)?.bonus(&content.items).health;
+            let max_health = max(1, progression::max_health(&config.game, 1, &user.attributes) + bonus);
+            print_user(world, &storage.reset_user(user, max_health, config.game.starting_gold, world.start.clone())?);
+        }
+    }
+    Ok(())
+}
+
+pub fn stats(config: &Config) -> Result {
+    let world = &Content::load(&config.game.content_path)?.world;
+    let mut storage = Storage::new(&config.storage)?;
+    let total = storage.count_users()?;
+    println!("Users: {}", total);
+    for (location, count) in storage.count_users_by_location()? {
+        println!("  {}: {}", location_name(world, &location), count);
+    }
+    Ok(())
+}
+
+/// The configuration has been validated when it was loaded, so this only reports on what is left.
+pub fn check_config(config: &Config) -> Result {
+    config.transport.tls_paths()?;
+    Content::load(&config.game.content_path)?;
+    let mut shown = config.clone();
+    shown.storage.password = "********".to_owned();
+    print!("{}", toml::to_string(&shown)?);
+    eprintln!("Configuration is valid");
+    Ok(())
+}
+
+/// Writes `contents` to a new file, or truncates an existing one when `force` is set.
+fn write_new_file(path: &Path, contents: &[u8], force: bool, private: bool) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options.open(path)?.write_all(contents)
+}
+
+pub fn gen_cert(config: &Config, args: GenCertArgs) -> Result {
+    let certificate_path = args.certificate_path
+        .or_else(|| config.transport.certificates_path.clone())
+        .unwrap_or_else(|| "namushul_certificate.pem".into());
+    let private_key_path = args.private_key_path
+        .or_else(|| config.transport.private_key_path.clone())
+        .unwrap_or_else(|| "namushul_private_key.pem".into());
+
+    let generated = certificate::generate(&args.hostname, args.days, args.key_type)?;
+    for (path, contents, private) in [
+        (&certificate_path, &generated.certificate_pem, false),
+        (&private_key_path, &generated.private_key_pem, true),
+    ] {
+        write_new_file(path, contents, args.force, private)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    }
+    println!("Wrote certificate for {} to {}", args.hostname, certificate_path.display());
+    println!("Wrote private key to {}", private_key_path.display());
+    Ok(())
+}
+
+/// Requests a URL, printing the header to stderr and the body to stdout, or to a file.
+/// Fails for the failure statuses, so that scripts can tell.
+pub fn fetch(args: FetchArgs) -> Result {
+    let identity = match (&args.certificate_path, &args.private_key_path) {
+        (Some(certificate_path), Some(private_key_path)) => Some(Identity::from_pem_files(certificate_path, private_key_path)?),
+        _ => None,
+    };
+    let client = Client::new(identity.as_ref())?;
+    let (url, response) = client.fetch(args.url, args.max_redirects)?;
+    eprintln!("{}", url);
+    eprintln!("{} {}", response.status.code(), response.meta);
+    match &args.output {
+        Some(path) => fs::write(path, &response.body)?,
+        None => io::stdout().write_all(&response.body)?,
+    }
+    match response.status.code() {
+        10..=39 => Ok(()),
+        _ if response.status == Status::SlowDown => Err(format!("slow down, wait {} seconds", response.meta).into()),
+        _ => Err(format!("request failed: {}", response.meta).into()),
+    }
+}
@@ -1,24 +1,43 @@
 //! This handles the business logic, decoupled from the actual transport layer.
 
-use std::env;
+use std::cmp::{max, min};
+use std::sync::Arc;
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
+use rand::seq::SliceRandom;
 use url::Url;
 
+use crate::accounts::{self, RecoverySecret};
+use crate::attributes::{Attribute, Attributes};
+use crate::combat::{Monster, Move, Outcome, Stats};
+use crate::combat;
+use crate::config::{Config, GameConfig};
+use crate::items::{Bonus, EquipError, Equipment, Inventory, Item, ItemKind, Slot};
+use crate::content::Content;
+use crate::dialogue::{Choice, Node, Npc};
 use crate::duration::Humanize;
+use crate::export::{CharacterExport, Export};
+use crate::progression;
+use crate::quests::{Event, Objective, Quest, QuestState};
+use crate::random::{Dice, Random};
 use crate::response::{Language, MediaType, Response};
-use crate::storage::{locations, Storage, User};
+use crate::shops::{self, Shop, Supply, TradeError};
+use crate::storage::{Account, Certificate, Draft, Encounter, HistoryEntry, QuestProgress, Storage, User};
 use crate::storage;
+use crate::world::{Action, Happening, Location, Refusal, Traveller};
 
 #[derive(Debug)]
 pub struct Application {
     start_time: Instant,
+    config: Arc<Config>,
+    content: Arc<Content>,
+    random: Random,
 }
 
 impl Application {
-    pub fn new(start_time: Instant) -> Self {
-        Self { start_time }
+    pub fn new(start_time: Instant, config: Arc<Config>, content: Arc<Content>, random: Random) -> Self {
+        Self { start_time, config, content, random }
     }
 }
 
@@ -47,65 +66,1159 @@ fn serve_landing(user: Option<User>) -> Response {
     )
 }
 
-fn bastow(user: User) -> Response {
+/// The character's name, level, health and gold, shown at the top of every adventure page.
+fn character_header(game: &GameConfig, user: &User) -> String {
+    let xp = match progression::xp_for(&game.level_xp, user.level + 1) {
+        Some(next) => format!("{}/{}", user.xp, next),
+        None => user.xp.to_string(),
+    };
+    let full_in = game.health_regeneration()
+        .and_then(|interval| progression::full_in(user, interval, SystemTime::now()))
+        .map(|duration| format!(" (full in {})", duration.humanize()))
+        .unwrap_or_default();
+    format!("### {}\r\nLevel {} · XP: {} · HP: {}/{}{} · Gold: {}\r\n",
+        user.name, user.level, xp, user.health, user.max_health, full_in, user.gold)
+}
+
+/// Where links to actions that change the game point to, carrying the user's action token so that
+/// each of them only works once.
+fn act(user: &User) -> String {
+    format!("/act/{}", user.actions)
+}
+
+/// Where the character is, and what they can do there. `log` tells what just happened, such as
+/// progress on quests.
+fn location_page(game: &GameConfig, content: &Content, user: &User, location: &Location, quests: &[QuestProgress], log: &[String]) -> Response {
+    let mut page = format!("{header}### {location}\r\n{description}\r\n",
+        header = character_header(game, user), location = location.name, description = location.description);
+    for line in log {
+        page.push_str(&format!("* {}\r\n", line));
+    }
+    if !location.exits.is_empty() {
+        page.push_str("### Travel\r\n");
+        for exit in &location.exits {
+            match exit.conditions(|id| content.item_name(id)) {
+                Some(conditions) => page.push_str(&format!("=> {}/adventure/go/{} {} ({})\r\n", act(user), exit.to, exit.label, conditions)),
+                None => page.push_str(&format!("=> {}/adventure/go/{} {}\r\n", act(user), exit.to, exit.label)),
+            }
+        }
+    }
+    if !location.actions.is_empty() {
+        page.push_str("### Actions\r\n");
+        for action in &location.actions {
+            match action {
+                Action::Rest => page.push_str(&format!("=> {}{} {} ({} gold)\r\n", act(user), action.path(), action.label(), game.rest_price)),
+                _ => page.push_str(&format!("=> {}{} {}\r\n", act(user), action.path(), action.label())),
+            }
+        }
+    }
+    let completed = quests.iter().filter(|progress| progress.completed).map(|progress| progress.quest.as_str()).collect::<Vec<_>>();
+    let mut offered = content.quests_at(&location.id)
+        .filter(|quest| match quests.iter().find(|progress| progress.quest == quest.id) {
+            Some(progress) => !progress.completed,
+            None => quest.available(user.level, &completed),
+        })
+        .peekable();
+    let mut npcs = content.npcs_at(&location.id).peekable();
+    if npcs.peek().is_some() {
+        page.push_str("### People\r\n");
+        for npc in npcs {
+            page.push_str(&format!("=> /adventure/talk/{} 💬 Talk to {}\r\n", npc.id, npc.name));
+        }
+    }
+    if offered.peek().is_some() {
+        page.push_str("### Quests\r\n");
+        for quest in offered {
+            page.push_str(&format!("=> /adventure/quests/{} 📜 {}: {}\r\n", quest.id, quest.giver, quest.name));
+        }
+    }
+    let mut shops = content.shops_at(&location.id).peekable();
+    if shops.peek().is_some() {
+        page.push_str("### Shops\r\n");
+        for shop in shops {
+            page.push_str(&format!("=> /adventure/shop/{} 🛒 {}\r\n", shop.id, shop.name));
+        }
+    }
+    page.push_str("=> /adventure/character 🧝 Character\r\n=> /adventure/inventory 🎒 Inventory\r\n=> /adventure/quests 📜 Quest log\r\n\
+        => /adventure/characters 👥 Switch character\r\n");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// What happened at the inn, be it a good night's sleep or being shown the door.
+fn rest_page(game: &GameConfig, user: &User, inn: &Location, message: &str) -> Response {
+    Response::success(
+        MediaType::gemini(Some(Language::english())),
+        format!("{}### {}\r\n{}\r\n=> /adventure Continue", character_header(game, user), inn.name, message),
+    )
+}
+
+fn refusal_page(content: &Content, destination: &Location, refusal: Refusal) -> Response {
+    let reason = match refusal {
+        Refusal::Level(level) => format!("You need to be level {} to go there.", level),
+        Refusal::Item(item) => format!("You need a {} to go there.", content.item_name(&item)),
+        Refusal::Health(health) => format!("The way there costs {} HP. You are too exhausted to go.", health),
+    };
     Response::success(
         MediaType::gemini(Some(Language::english())),
-        format!("\
-        ### {name}\r\nHP: {health}/{max_health}\r\n\
-        ### Bastow \r\nYou are in the small port town of Bastow. The town has an inn. A small gravel path leads out of town and into the forest.\r\n\
-        ### Travel\r\n=> /adventure/bastow-woodlands 🌳 Follow the path into the forest.\r\n\
-        ### Actions\r\n=> /adventure/rest 🛏 Rest at the inn.", name = user.name, health = user.health, max_health = user.max_health),
+        format!("### {}\r\n{}\r\n=> /adventure Back", destination.name, reason),
     )
 }
 
-fn bastow_woodlands(user: User) -> Response {
+fn fight_page(game: &GameConfig, user: &User, monster: &Monster, encounter: &Encounter, log: &[String]) -> Response {
+    let mut page = format!("{header}### Fighting the {monster}\r\n{description}\r\nHP of the {monster}: {health}/{max_health}\r\n",
+        header = character_header(game, user), monster = monster.name, description = monster.description,
+        health = encounter.monster_health, max_health = monster.health);
+    // Before the first round, the log tells how the fight came about.
+    if !log.is_empty() && encounter.round > 0 {
+        page.push_str(&format!("### Round {}\r\n", encounter.round));
+    }
+    for line in log {
+        page.push_str(&format!("* {}\r\n", line));
+    }
+    page.push_str(&format!("### Actions\r\n\
+        => {act}/adventure/fight/attack ⚔ Attack\r\n\
+        => {act}/adventure/fight/defend 🛡 Defend\r\n\
+        => {act}/adventure/fight/flee 🏃 Flee\r\n", act = act(user)));
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// The page shown once a fight is won or fled from, `round` having decided it. `loot` tells what
+/// became of the loot.
+fn fight_over_page(game: &GameConfig, user: &User, monster: &Monster, round: &combat::Round, loot: &[String]) -> Response {
+    let mut page = character_header(game, user);
+    page.push_str(match round.outcome {
+        Outcome::Victory { .. } => "### Victory\r\n",
+        _ => "### Escape\r\n",
+    });
+    for line in &round.log {
+        page.push_str(&format!("* {}\r\n", line));
+    }
+    if let Outcome::Victory { .. } = &round.outcome {
+        if loot.is_empty() {
+            page.push_str(&format!("The {} left nothing behind.\r\n", monster.name));
+        } else {
+            page.push_str("### Loot\r\n");
+            for line in loot {
+                page.push_str(&format!("* {}\r\n", line));
+            }
+        }
+    }
+    page.push_str("=> /adventure Continue");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// Stats of a character wearing equipment that adds up to `bonus`.
+fn character_stats(game: &GameConfig, user: &User, bonus: Bonus) -> Stats {
+    let stats = progression::stats(game, user.level, &user.attributes);
+    Stats { attack: stats.attack + bonus.attack, defense: stats.defense + bonus.defense }
+}
+
+fn bonus_text(bonus: Bonus) -> String {
+    let parts = [(bonus.attack, "attack"), (bonus.defense, "defense"), (bonus.health, "max HP")];
+    let parts = parts.iter()
+        .filter(|(value, _)| *value != 0)
+        .map(|(value, name)| format!("{:+} {}", value, name))
+        .collect::<Vec<_>>();
+    if parts.is_empty() { "no bonus".to_owned() } else { parts.join(", ") }
+}
+
+fn equip_error_text(error: EquipError, item: &str) -> String {
+    match error {
+        EquipError::NotEquipment => format!("The {} cannot be worn.", item),
+        EquipError::NotCarried => format!("You do not carry a {}.", item),
+        EquipError::Empty => "You wear nothing there.".to_owned(),
+        EquipError::NoRoom => "Your bags are full. Make room before taking anything off.".to_owned(),
+    }
+}
+
+/// Stats, attributes and equipment of the character, with a preview of how the equipment they
+/// carry would change their stats. `note` tells how the last change went.
+fn character_page(game: &GameConfig, content: &Content, user: &User, inventory: &Inventory, equipment: &Equipment, note: Option<&str>) -> Response {
+    let bonus = equipment.bonus(&content.items);
+    let stats = character_stats(game, user, bonus);
+    let attributes = &user.attributes;
+    let mut page = character_header(game, user);
+    page.push_str(&format!("### Character\r\nAttack: {} · Defense: {} · Max HP: {}\r\n{}: {} · {}: {} · {}: {}\r\n",
+        stats.attack, stats.defense, user.max_health,
+        Attribute::Strength.label(), attributes.strength,
+        Attribute::Agility.label(), attributes.agility,
+        Attribute::Vitality.label(), attributes.vitality));
+    if let Some(note) = note {
+        page.push_str(&format!("{}\r\n", note));
+    }
+
+    page.push_str("### Equipment\r\n");
+    for slot in Slot::ALL {
+        match equipment.get(slot) {
+            Some(id) => {
+                let (name, item_bonus) = content.item(id).map_or((id, Bonus::default()), |item| (&item.name, item.bonus));
+                page.push_str(&format!("{}: {} ({})\r\n=> {}/adventure/character/unequip/{} Take off the {}\r\n",
+                    slot.label(), name, bonus_text(item_bonus), act(user), slot.segment(), name));
+            }
+            None => page.push_str(&format!("{}: nothing\r\n", slot.label())),
+        }
+    }
+
+    let wearable = inventory.entries().iter()
+        .filter_map(|(id, _)| content.item(id))
+        .filter_map(|item| Some((item, item.slot?)))
+        .collect::<Vec<(&Item, Slot)>>();
+    if !wearable.is_empty() {
+        page.push_str("### In your bags\r\n");
+    }
+    for (item, slot) in wearable {
+        let replaced = equipment.get(slot).and_then(|id| content.item(id)).map_or(Bonus::default(), |worn| worn.bonus);
+        let change = item.bonus - replaced;
+        let after = character_stats(game, user, bonus + change);
+        let preview = [
+            ("Attack", stats.attack, after.attack),
+            ("Defense", stats.defense, after.defense),
+            ("Max HP", user.max_health, user.max_health + change.health),
+        ];
+        let preview = preview.iter()
+            .filter(|(_, before, after)| before != after)
+            .map(|(name, before, after)| format!("{} {} → {}", name, before, after))
+            .collect::<Vec<_>>();
+        let preview = if preview.is_empty() { "no change".to_owned() } else { preview.join(", ") };
+        page.push_str(&format!("=> {}/adventure/character/equip/{} Wear the {} ({})\r\n", act(user), item.id, item.name, preview));
+    }
+    page.push_str("=> /adventure Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// What the character carries. `note` tells how the last use or drop went.
+fn inventory_page(game: &GameConfig, content: &Content, user: &User, inventory: &Inventory, note: Option<&str>) -> Response {
+    let mut page = character_header(game, user);
+    page.push_str(&format!("### Inventory\r\nSlots: {}/{}\r\n", inventory.slots_used(&content.items), game.inventory_slots));
+    if let Some(note) = note {
+        page.push_str(&format!("{}\r\n", note));
+    }
+    if inventory.entries().is_empty() {
+        page.push_str("Your bags are empty.\r\n");
+    }
+    for (id, quantity) in inventory.entries() {
+        match content.item(id) {
+            Some(item) => {
+                page.push_str(&format!("### {} × {}\r\n{}\r\n{}, worth {} gold\r\n",
+                    item.name, quantity, item.description, item.kind.label(), item.value));
+                match item.kind {
+                    ItemKind::Consumable => page.push_str(&format!("=> {}/adventure/inventory/use/{} 🍴 Use a {}\r\n", act(user), id, item.name)),
+                    ItemKind::Equipment => page.push_str(&format!("=> {}/adventure/character/equip/{} 🧥 Wear the {}\r\n", act(user), id, item.name)),
+                    _ => {}
+                }
+                page.push_str(&format!("=> {}/adventure/inventory/drop/{} 🗑 Drop a {}\r\n", act(user), id, item.name));
+            }
+            // The item has been removed from the content since, all that can be done is to drop it.
+            None => page.push_str(&format!("### {} × {}\r\nSomething strange.\r\n=> {}/adventure/inventory/drop/{} 🗑 Drop it\r\n", id, quantity, act(user), id)),
+        }
+    }
+    page.push_str("=> /adventure Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// The storefront: what the shop sells, and what it would buy from the character. `supplies` is
+/// what is left of limited stock, as stored.
+fn shop_page(game: &GameConfig, content: &Content, user: &User, shop: &Shop, supplies: &[(String, Supply)], inventory: &Inventory, note: Option<&str>) -> Response {
+    let now = SystemTime::now();
+    let mut page = format!("{}### {}\r\n{}\r\n", character_header(game, user), shop.name, shop.description);
+    if let Some(note) = note {
+        page.push_str(&format!("{}\r\n", note));
+    }
+    page.push_str("### For sale\r\n");
+    for stock in &shop.stock {
+        let item = match content.item(&stock.item) {
+            Some(item) => item,
+            None => continue,
+        };
+        let stored = supplies.iter().find(|(id, _)| *id == item.id).map(|&(_, supply)| supply);
+        let price = stock.price(item);
+        match stock.supply(stored, now) {
+            Some(supply) if supply.remaining == 0 => match stock.restocks_in(&supply, now) {
+                Some(duration) => page.push_str(&format!("* {}, {} gold: sold out, back in {}\r\n", item.name, price, duration.humanize())),
+                None => page.push_str(&format!("* {}, {} gold: sold out\r\n", item.name, price)),
+            },
+            Some(supply) => page.push_str(&format!("=> {}/adventure/shop/{}/buy/{} 🪙 Buy a {} for {} gold ({} left)\r\n",
+                act(user), shop.id, item.id, item.name, price, supply.remaining)),
+            None => page.push_str(&format!("=> {}/adventure/shop/{}/buy/{} 🪙 Buy a {} for {} gold\r\n", act(user), shop.id, item.id, item.name, price)),
+        }
+        page.push_str(&format!("{}\r\n", item.description));
+    }
+    page.push_str("### Wanted\r\n");
+    let mut wanted = false;
+    for (id, quantity) in inventory.entries() {
+        if let Some((item, price)) = content.item(id).and_then(|item| Some((item, shop.offer(item)?))) {
+            page.push_str(&format!("=> {}/adventure/shop/{}/sell/{} 💰 Sell a {} for {} gold (you have {})\r\n", act(user), shop.id, id, item.name, price, quantity));
+            wanted = true;
+        }
+    }
+    if !wanted {
+        page.push_str("You carry nothing the shop wants.\r\n");
+    }
+    page.push_str("=> /adventure Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+fn trade_error_text(error: TradeError, item: &str) -> String {
+    match error {
+        TradeError::OutOfStock(0) => format!("The {} is sold out.", item),
+        TradeError::OutOfStock(left) => format!("There are only {} × {} left.", left, item),
+        TradeError::NotEnoughGold => "You do not have enough gold.".to_owned(),
+        TradeError::NoRoom => format!("Your bags have no room for that many {}.", item),
+        TradeError::NotCarried => format!("You do not have that many {}.", item),
+        TradeError::NotWanted => format!("The shop has no interest in the {}.", item),
+    }
+}
+
+/// A conversation with a non-player character, at `node`. `choices` are those the character may
+/// make, by their position in the node. `log` tells what the last choice did.
+fn conversation_page(game: &GameConfig, user: &User, npc: &Npc, node: &Node, choices: &[(usize, &Choice)], log: &[String]) -> Response {
+    let mut page = format!("{}### {}\r\n{}\r\n", character_header(game, user), npc.name, npc.description);
+    for line in log {
+        page.push_str(&format!("* {}\r\n", line));
+    }
+    page.push_str(&format!("> {}\r\n", node.text));
+    for (i, choice) in choices {
+        page.push_str(&format!("=> {}/adventure/talk/{}/{} 💬 {}\r\n", act(user), npc.id, i, choice.label));
+    }
+    page.push_str("=> /adventure Walk away");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// An objective and how far it got, such as "Defeat 3 × giant rat (1/3)".
+fn objective_text(content: &Content, objective: &Objective, progress: i32) -> String {
+    let text = match objective {
+        Objective::Kill { monster, count } =>
+            format!("Defeat {} × {}", count, content.monster(monster).map_or(monster.as_str(), |monster| &monster.name)),
+        Objective::Reach { location } =>
+            format!("Travel to {}", content.world.location(location).map_or(location.as_str(), |location| &location.name)),
+        Objective::Deliver { item, count } => format!("Bring {} × {}", count, content.item_name(item)),
+    };
+    let mark = if progress >= objective.target() { "✅ " } else { "" };
+    format!("{}{} ({}/{})", mark, text, progress, objective.target())
+}
+
+/// A single quest: what it asks for and what it pays, with links to take it on or hand it in.
+/// `progress` is `None` when the character has not taken the quest on yet.
+fn quest_page(game: &GameConfig, content: &Content, user: &User, quest: &Quest, progress: Option<&QuestProgress>, inventory: &Inventory, note: Option<&str>) -> Response {
+    let location = content.world.location(&quest.location).map_or(quest.location.as_str(), |location| &location.name);
+    let mut page = format!("{}### {}\r\n{}, {}:\r\n> {}\r\n",
+        character_header(game, user), quest.name, quest.giver, location, quest.description);
+    if let Some(note) = note {
+        page.push_str(&format!("{}\r\n", note));
+    }
+    page.push_str("### Objectives\r\n");
+    let counts = progress.map_or(&[][..], |progress| &progress.progress);
+    for (i, objective) in quest.objectives.iter().enumerate() {
+        // Delivered items are gone from the inventory once the quest is completed.
+        let count = match progress {
+            Some(progress) if progress.completed => objective.target(),
+            _ => objective.progress(counts.get(i).copied().unwrap_or(0), inventory),
+        };
+        page.push_str(&format!("* {}\r\n", objective_text(content, objective, count)));
+    }
+    let rewards = &quest.rewards;
+    page.push_str("### Rewards\r\n");
+    if rewards.xp > 0 {
+        page.push_str(&format!("* {} XP\r\n", rewards.xp));
+    }
+    if rewards.gold > 0 {
+        page.push_str(&format!("* {} gold\r\n", rewards.gold));
+    }
+    for reward in &rewards.items {
+        page.push_str(&format!("* {} × {}\r\n", content.item_name(&reward.item), reward.quantity));
+    }
+    let here = user.location == quest.location;
+    match progress {
+        Some(progress) if progress.completed => page.push_str("You have completed this quest.\r\n"),
+        Some(_) if !quest.done(counts, inventory) => {}
+        Some(_) if here => page.push_str(&format!("=> {}/adventure/quests/{}/complete 🏁 Hand in the quest\r\n", act(user), quest.id)),
+        Some(_) => page.push_str(&format!("Return to {} in {} to hand in the quest.\r\n", quest.giver, location)),
+        None if here => page.push_str(&format!("=> {}/adventure/quests/{}/accept 🤝 Accept the quest\r\n", act(user), quest.id)),
+        None => {}
+    }
+    page.push_str("=> /adventure/quests 📜 Quest log\r\n=> /adventure Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// The quests the character has taken on, open ones first.
+fn quest_log_page(game: &GameConfig, content: &Content, user: &User, quests: &[QuestProgress], inventory: &Inventory) -> Response {
+    let mut page = format!("{}### Quest log\r\n", character_header(game, user));
+    let (completed, open): (Vec<_>, Vec<_>) = quests.iter()
+        .filter_map(|progress| Some((content.quest(&progress.quest)?, progress)))
+        .partition(|(_, progress)| progress.completed);
+    if open.is_empty() {
+        page.push_str("You have no open quests. Ask around town for work.\r\n");
+    }
+    for (quest, progress) in open {
+        let state = if quest.done(&progress.progress, inventory) { "ready to hand in" } else { "in progress" };
+        page.push_str(&format!("=> /adventure/quests/{} {} ({})\r\n", quest.id, quest.name, state));
+    }
+    if !completed.is_empty() {
+        page.push_str("### Completed\r\n");
+        for (quest, _) in completed {
+            page.push_str(&format!("=> /adventure/quests/{} {}\r\n", quest.id, quest.name));
+        }
+    }
+    page.push_str("=> /adventure Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// A quantity entered in response to an input prompt. Only whole numbers above zero count.
+fn parse_quantity(query: Option<&str>) -> Option<i32> {
+    query?.trim().parse().ok().filter(|&quantity| quantity > 0)
+}
+
+/// The second step of character creation, where points are spent on attributes.
+fn creation_page(game: &GameConfig, draft: &Draft) -> Response {
+    let attributes = &draft.attributes;
+    let points_left = attributes.points_left(game);
+    let mut page = format!("### Create your character\r\nName: {}\r\n=> /adventure/create/name 📝 Change name\r\n\
+        ### Attributes\r\nPoints left: {}\r\n", draft.name, points_left);
+    for attribute in Attribute::ALL {
+        let value = attributes.get(attribute);
+        page.push_str(&format!("{}: {}\r\n", attribute.label(), value));
+        if attributes.spend(game, attribute, 1).is_some() {
+            page.push_str(&format!("=> /adventure/create/{}/add ➕ More {}\r\n", attribute.segment(), attribute.segment()));
+        }
+        if attributes.spend(game, attribute, -1).is_some() {
+            page.push_str(&format!("=> /adventure/create/{}/remove ➖ Less {}\r\n", attribute.segment(), attribute.segment()));
+        }
+    }
+    let stats = progression::stats(game, 1, attributes);
+    page.push_str(&format!("Strength adds to attack, agility to defense and vitality to health.\r\n\
+        ### Your character\r\nHP: {} · Attack: {} · Defense: {}\r\n",
+        progression::max_health(game, 1, attributes), stats.attack, stats.defense));
+    if points_left == 0 {
+        page.push_str("=> /adventure/create/done ✅ Begin your adventure");
+    } else {
+        page.push_str("Spend all your points to begin your adventure.");
+    }
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// The characters of an account, to pick the one to play, and room for a new one. `note` tells how
+/// the last change went.
+fn characters_page(game: &GameConfig, content: &Content, account: &Account, characters: &[User], note: Option<&str>) -> Response {
+    let mut page = "### Characters\r\n".to_owned();
+    if let Some(note) = note {
+        page.push_str(&format!("{}\r\n", note));
+    }
+    for character in characters {
+        let location = content.world.location_or_start(&character.location);
+        page.push_str(&format!("### {}\r\nLevel {} · {}\r\n", character.name, character.level, location.name));
+        if account.active_user == Some(character.id) {
+            page.push_str("=> /adventure ▶ Continue playing\r\n");
+        } else {
+            page.push_str(&format!("=> /adventure/characters/{} ▶ Play {}\r\n", character.id, character.name));
+        }
+        page.push_str(&format!("=> /adventure/characters/{}/delete 🗑 Delete {}\r\n", character.id, character.name));
+    }
+    page.push_str("### New character\r\n");
+    if characters.len() < game.max_characters as usize {
+        page.push_str(&format!("=> /adventure/create ➕ Create a character ({} of {})", characters.len() + 1, game.max_characters));
+    } else {
+        page.push_str(&format!("You have {} characters, as many as you may. Delete one to make room.", characters.len()));
+    }
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// The page shown after a knockout, `log` telling what led to it.
+fn wake_up_page(game: &GameConfig, user: &User, inn: &Location, log: &[String]) -> Response {
+    let mut page = character_header(game, user);
+    page.push_str("### Knocked out\r\n");
+    for line in log {
+        page.push_str(&format!("* {}\r\n", line));
+    }
+    page.push_str(&format!("Everything goes dark.\r\n\r\nYou wake up at {}, bruised but alive.\r\n=> /adventure Get up", inn.name));
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// What visitors without a character see: they may create one, or play the characters of a
+/// certificate they use elsewhere.
+fn welcome_page() -> Response {
     Response::success(
         MediaType::gemini(Some(Language::english())),
-        format!("\
-        ### {name}\r\nHP: {health}/{max_health}\r\n\
-        ### Bastow Woodlands \r\nYou are in Bastow Woodland. You see nothing of interest.\r\n\
-        ### Travel\r\n=> /adventure/bastow 👣 Go back to Bastow.\r\n\
-        ### Actions\r\n=> /adventure/fight 👊 Fight slimes.", name = user.name, health = user.health, max_health = user.max_health),
+        "### Welcome, traveller\r\nYou have no character yet.\r\n\
+            => /adventure/create ✨ Create a character\r\n\
+            => /account/certificates/link 🔗 Play the characters of a certificate you use elsewhere\r\n\
+            => /account/recover 🗝 Recover an account whose certificates are lost".to_owned(),
     )
 }
 
-fn status_page(user: User) -> Response {
-    match user.location_id {
-        locations::BASTOW_WOODLANDS => bastow_woodlands(user),
-        locations::BASTOW | _ => bastow(user),
+/// The certificates an account is played with, `current` being the one in use. `note` tells how
+/// the last change went.
+fn certificates_page(certificates: &[Certificate], current: &[u8], note: Option<&str>) -> Response {
+    let mut page = "### Certificates\r\n".to_owned();
+    if let Some(note) = note {
+        page.push_str(&format!("{}\r\n", note));
+    }
+    page.push_str("Each of these certificates plays this account, with all of its characters.\r\n");
+    let now = SystemTime::now();
+    for certificate in certificates {
+        let short = accounts::short_fingerprint(&certificate.fingerprint);
+        let ago = now.duration_since(certificate.linked_at).unwrap_or_default().humanize();
+        if certificate.fingerprint == current {
+            page.push_str(&format!("* {} (this one), linked {} ago\r\n", short, ago));
+        } else {
+            page.push_str(&format!("=> /account/certificates/{}/revoke 🗑 Revoke {}, linked {} ago\r\n",
+                accounts::fingerprint_hex(&certificate.fingerprint), short, ago));
+        }
+    }
+    page.push_str("### Link another certificate\r\n\
+        => /account/certificates/pair 🔗 Make a pairing code for it\r\n\
+        ### Play another account\r\n\
+        => /account/certificates/link 🔑 Enter a pairing code made with one of its certificates\r\n\
+        ### Recovery\r\n\
+        => /account/recovery 🗝 Recover the account when every certificate is lost\r\n\
+        => /account Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// Whether the account has a recovery secret, and setting it. `note` tells how the last change went.
+fn recovery_page(account: &Account, has_secret: bool, note: Option<&str>) -> Response {
+    let mut page = "### Recovery\r\n".to_owned();
+    if let Some(note) = note {
+        page.push_str(&format!("{}\r\n", note));
+    }
+    page.push_str(&format!("Should every certificate this account is played with be lost, another certificate can recover it \
+        with the account number and the recovery secret.\r\nAccount number: {}\r\n", account.id));
+    if has_secret {
+        page.push_str("Recovery secret: set\r\n\
+            => /account/recovery/set 🔒 Change the recovery secret\r\n\
+            => /account/recovery/remove 🗑 Remove the recovery secret\r\n");
+    } else {
+        page.push_str("Recovery secret: not set, so the account cannot be recovered\r\n\
+            => /account/recovery/set 🔒 Set a recovery secret\r\n");
+    }
+    page.push_str("=> /account/certificates Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+fn history_page(history: &[HistoryEntry]) -> Response {
+    let mut page = "### History\r\n".to_owned();
+    if history.is_empty() {
+        page.push_str("Nothing worth telling has happened yet.\r\n");
+    }
+    let now = SystemTime::now();
+    for entry in history {
+        let icon = match entry.kind.as_str() {
+            "knocked-out" => "💀",
+            "level-up" => "🎉",
+            _ => "📜",
+        };
+        let ago = now.duration_since(entry.happened_at).unwrap_or_default().humanize();
+        page.push_str(&format!("* {} {} ({} ago)\r\n", icon, entry.description, ago));
+    }
+    page.push_str("=> /account Back");
+    Response::success(MediaType::gemini(Some(Language::english())), page)
+}
+
+/// Splits the action token off the front of a path such as `act/12/adventure/rest`, see [act].
+/// A token that is not a number is no token at all.
+fn split_action_token(path_segments: Vec<&str>) -> (Option<i64>, Vec<&str>) {
+    match path_segments[..] {
+        ["act", token, ..] => (token.parse().ok(), path_segments[2..].to_vec()),
+        _ => (None, path_segments),
+    }
+}
+
+/// Whether a request changes the game, and must carry an action token. Buying and selling only do
+/// once they are told how many, so the token is still good when the question is answered.
+fn changes_state(path_segments: &[&str], query: Option<&str>) -> bool {
+    match path_segments {
+        ["adventure", "fight", ..] | ["adventure", "rest"] | ["adventure", "explore"] | ["adventure", "go", _]
+        | ["adventure", "character", _, _] | ["adventure", "inventory", _, _]
+        | ["adventure", "quests", _, _] | ["adventure", "talk", _, _] => true,
+        ["adventure", "shop", _, _, _] => query.is_some(),
+        _ => false,
+    }
+}
+
+/// Whether the player typed `word` to confirm something that cannot be undone, which a stray click
+/// or a prefetched link cannot do, or `None` until they are asked.
+fn confirmed(query: Option<&str>, word: &str) -> Option<bool> {
+    query.map(|answer| answer.trim() == word)
+}
+
+/// Whether what is entered on the page at `path_segments` is a secret, such as a recovery secret
+/// or a pairing code, and must be kept out of the log.
+fn has_secret_query(path_segments: &[&str]) -> bool {
+    matches!(path_segments,
+        ["account", "recovery", "set"] | ["account", "recover", _] | ["account", "certificates", "pair" | "link"])
+}
+
+/// Gathers everything kept about the account, see [Export].
+fn gather_export(storage: &mut Storage, account: &Account) -> Result<Export, storage::Error> {
+    let mut characters = vec![];
+    for user in storage.list_characters(account)? {
+        characters.push(CharacterExport {
+            equipment: storage.get_equipment(&user)?,
+            inventory: storage.get_inventory(&user)?,
+            quests: storage.get_quests(&user)?,
+            history: storage.get_history(&user, i64::MAX)?,
+            user,
+        });
+    }
+    Ok(Export {
+        account: account.clone(),
+        certificates: storage.list_certificates(account)?,
+        has_recovery_secret: storage.has_recovery_secret(account)?,
+        characters,
+    })
+}
+
+/// Chooses how to answer when the storage layer fails, `message` describing what was attempted.
+fn storage_failure(message: &str, error: storage::Error) -> Response {
+    eprintln!("{}: {}", message, error);
+    match error {
+        storage::Error::Connection(_) => Response::server_unavailable("The database is unavailable, please try again later".into()),
+        storage::Error::SerializationFailure => Response::temporary_failure(format!("{}, please try again", message)),
+        storage::Error::UniqueViolation(_) | storage::Error::CheckViolation(_) => Response::permanent_failure(message.into()),
+        storage::Error::NotFound => Response::not_found(message.into()),
+        storage::Error::Db(_) | storage::Error::MissingPrimaryKeyRow => Response::temporary_failure(message.into()),
     }
 }
 
+/// How many entries of their history a user gets to see.
+const HISTORY_LENGTH: i64 = 20;
 
 impl Application {
-    pub fn handle_request(&self, request: Request) -> Response {
-        eprintln!("Request: {}", request.url);
-        eprintln!("Request-path: {}", request.url.path());
-        eprintln!("Request-query: {:?}", request.url.query_pairs().map(|(k, v)| { format!("{}: {}", k, v) }).collect::<Vec<String>>());
-        eprintln!("Request-query: {:?}", request.query);
+    /// Walks a visitor without a character through creating one: they choose a name, then spend
+    /// their points on attributes. What they chose so far is kept as a draft.
+    fn create_character(&self, storage: &mut Storage, account: &Account, path_segments: &[&str], query: Option<String>) -> Response {
+        let game = &self.config.game;
+        let characters = match storage.list_characters(account) {
+            Ok(characters) => characters,
+            Err(e) => return storage_failure("Failed to get characters", e),
+        };
+        if characters.len() >= game.max_characters as usize {
+            return characters_page(game, &self.content, account, &characters, None);
+        }
+        let draft = match storage.get_draft(account) {
+            Ok(draft) => draft,
+            Err(e) => return storage_failure("Failed to get character", e),
+        };
+        let save = |storage: &mut Storage, draft: Draft| match storage.save_draft(account, &draft) {
+            Ok(()) => Response::redirect_temporary("/adventure/create".to_owned()),
+            Err(e) => storage_failure("Failed to save character", e),
+        };
+        let draft = match draft {
+            Some(draft) => draft,
+            None => return match (path_segments, query) {
+                (["adventure", "create", ..], None) => Response::input("Choose a name for your character".to_owned()),
+                (["adventure", "create", ..], Some(name)) => save(storage, Draft { name, attributes: Attributes::default() }),
+                _ => welcome_page(),
+            },
+        };
 
-        let simulate_latency = env::var("SIMULATE_LATENCY").unwrap_or("false".to_owned());
-        if simulate_latency == "true" {
-            sleep(Duration::from_secs(1));
+        match *path_segments {
+            ["adventure", "create", "name"] => match query {
+                None => Response::input("Choose a name for your character".to_owned()),
+                Some(name) => save(storage, Draft { name, ..draft }),
+            },
+            ["adventure", "create", "done"] => {
+                if draft.attributes.points_left(game) != 0 {
+                    return Response::redirect_temporary("/adventure/create".to_owned());
+                }
+                let max_health = progression::max_health(game, 1, &draft.attributes);
+                match storage.create_user(account, draft, max_health, game.starting_gold, self.content.world.start.clone()) {
+                    Ok(_user) => Response::redirect_temporary("/adventure".to_owned()),
+                    // Another request with the same certificate created the character first.
+                    Err(storage::Error::NotFound) => Response::redirect_temporary("/adventure".to_owned()),
+                    Err(e) => storage_failure("Failed to create user", e),
+                }
+            }
+            ["adventure", "create", attribute, change] => {
+                let attribute = match Attribute::from_segment(attribute) {
+                    Some(attribute) => attribute,
+                    None => return Response::not_found("There is no such attribute".to_owned()),
+                };
+                let change = match change {
+                    "add" => 1,
+                    "remove" => -1,
+                    _ => return Response::not_found("".to_owned()),
+                };
+                match draft.attributes.spend(game, attribute, change) {
+                    Some(attributes) => save(storage, Draft { attributes, ..draft }),
+                    // Out of points, or at a limit. Showing the page again makes that clear.
+                    None => Response::redirect_temporary("/adventure/create".to_owned()),
+                }
+            }
+            _ => creation_page(game, &draft),
+        }
+    }
+
+    /// The characters of the account, to pick one to play or to delete one.
+    fn manage_characters(&self, storage: &mut Storage, account: &Account, path_segments: &[&str], query: Option<String>) -> Response {
+        let game = &self.config.game;
+        let characters = match storage.list_characters(account) {
+            Ok(characters) => characters,
+            Err(e) => return storage_failure("Failed to get characters", e),
+        };
+        let character = match *path_segments {
+            ["adventure", "characters"] => return characters_page(game, &self.content, account, &characters, None),
+            ["adventure", "characters", id, ..] => match characters.iter().find(|character| character.id.to_string() == id) {
+                Some(character) => character,
+                None => return Response::not_found("There is no such character".to_owned()),
+            },
+            _ => return Response::not_found("".to_owned()),
+        };
+        match path_segments[3..] {
+            [] => match storage.select_character(account, character.id) {
+                Ok(()) => Response::redirect_temporary("/adventure".to_owned()),
+                Err(e) => storage_failure("Failed to select character", e),
+            },
+            ["delete"] => match confirmed(query.as_deref(), "DELETE") {
+                None => Response::input(format!(
+                    "Everything {} carries and has done will be lost for good. Type DELETE to confirm", character.name)),
+                Some(true) => match storage.delete_character(account, character.id) {
+                    Ok(()) => {
+                        let id = character.id;
+                        let note = format!("{} is no more.", character.name);
+                        let account = Account { active_user: account.active_user.filter(|&active| active != id), ..account.clone() };
+                        let characters = characters.iter().filter(|character| character.id != id).cloned().collect::<Vec<_>>();
+                        characters_page(game, &self.content, &account, &characters, Some(&note))
+                    }
+                    Err(e) => storage_failure("Failed to delete character", e),
+                },
+                Some(false) => Response::success(
+                    MediaType::gemini(Some(Language::english())),
+                    format!("### Delete {name}\r\n{name} was not deleted.\r\n=> /adventure/characters Back", name = character.name),
+                ),
+            },
+            _ => Response::not_found("".to_owned()),
+        }
+    }
+
+    /// The certificates the account is played with, and linking another one with a pairing code.
+    fn manage_certificates(&self, storage: &mut Storage, account: &Account, fingerprint: &[u8], path_segments: &[&str], query: Option<String>) -> Response {
+        let certificates = match storage.list_certificates(account) {
+            Ok(certificates) => certificates,
+            Err(e) => return storage_failure("Failed to get certificates", e),
+        };
+        match *path_segments {
+            ["account", "certificates"] => certificates_page(&certificates, fingerprint, None),
+            ["account", "certificates", "pair"] => {
+                let minutes = self.config.game.pairing_code_minutes;
+                // Not the game's dice, which can be replayed.
+                let code = accounts::pairing_code(&mut rand::thread_rng());
+                match storage.set_pairing_code(account, &code, Duration::from_secs(minutes * 60)) {
+                    Ok(()) => Response::success(
+                        MediaType::gemini(Some(Language::english())),
+                        format!("### Pairing code\r\n{}\r\n\
+                            With the certificate to link, such as on your other device, open /account/certificates/link \
+                            and enter this code within {} minutes. It works once.\r\n\
+                            => /account/certificates Back", accounts::show_pairing_code(&code), minutes),
+                    ),
+                    Err(e) => storage_failure("Failed to make pairing code", e),
+                }
+            }
+            ["account", "certificates", "link"] => {
+                let code = match query {
+                    Some(entered) => accounts::parse_pairing_code(&entered),
+                    None => return Response::input("Enter the pairing code made with your other certificate".to_owned()),
+                };
+                match storage.link_certificate(fingerprint, &code) {
+                    Ok(account) => match storage.list_certificates(&account) {
+                        Ok(certificates) => certificates_page(&certificates, fingerprint, Some("This certificate now plays the account of the pairing code.")),
+                        Err(e) => storage_failure("Failed to get certificates", e),
+                    },
+                    Err(storage::Error::NotFound) => Response::success(
+                        MediaType::gemini(Some(Language::english())),
+                        "### Pairing code\r\nThat pairing code is wrong, or has expired. Make a new one with your other certificate.\r\n\
+                            => /account/certificates/link Try again\r\n=> /adventure Back".to_owned(),
+                    ),
+                    Err(e) => storage_failure("Failed to link certificate", e),
+                }
+            }
+            ["account", "certificates", hex, "revoke"] => {
+                let certificate = match accounts::parse_fingerprint(hex).and_then(|revoked| certificates.iter().find(|certificate| certificate.fingerprint == revoked)) {
+                    Some(certificate) => certificate,
+                    None => return Response::not_found("There is no such certificate".to_owned()),
+                };
+                if certificate.fingerprint == fingerprint {
+                    return Response::bad_request("This is the certificate you are using".to_owned());
+                }
+                let short = accounts::short_fingerprint(&certificate.fingerprint);
+                match confirmed(query.as_deref(), "REVOKE") {
+                    None => Response::input(format!(
+                        "Certificate {} will no longer play this account, or any of its characters. Type REVOKE to confirm", short)),
+                    Some(true) => match storage.revoke_certificate(account, &certificate.fingerprint) {
+                        Ok(()) => match storage.list_certificates(account) {
+                            Ok(certificates) => certificates_page(&certificates, fingerprint, Some(&format!("Certificate {} was revoked.", short))),
+                            Err(e) => storage_failure("Failed to get certificates", e),
+                        },
+                        Err(e) => storage_failure("Failed to revoke certificate", e),
+                    },
+                    Some(false) => certificates_page(&certificates, fingerprint, Some(&format!("Certificate {} was not revoked.", short))),
+                }
+            }
+            _ => Response::not_found("".to_owned()),
+        }
+    }
+
+    /// Setting the recovery secret of the account, which lets another certificate claim it.
+    fn manage_recovery(&self, storage: &mut Storage, account: &Account, path_segments: &[&str], query: Option<String>) -> Response {
+        let secret = match *path_segments {
+            ["account", "recovery"] => return match storage.has_recovery_secret(account) {
+                Ok(has_secret) => recovery_page(account, has_secret, None),
+                Err(e) => storage_failure("Failed to get recovery secret", e),
+            },
+            ["account", "recovery", "set"] => match query {
+                Some(secret) if secret.chars().count() >= accounts::MIN_SECRET_LENGTH => secret,
+                Some(_) => return Response::sensitive_input(format!("That is too short. Choose a recovery secret of at least {} characters",
+                    accounts::MIN_SECRET_LENGTH)),
+                None => return Response::sensitive_input(format!("Choose a recovery secret of at least {} characters",
+                    accounts::MIN_SECRET_LENGTH)),
+            },
+            ["account", "recovery", "remove"] => return match confirmed(query.as_deref(), "REMOVE") {
+                None => Response::input("Without the recovery secret, the account is lost along with its certificates. \
+                    Type REMOVE to confirm".to_owned()),
+                Some(true) => match storage.set_recovery_secret(account, None) {
+                    Ok(()) => recovery_page(account, false, Some("The recovery secret is removed.")),
+                    Err(e) => storage_failure("Failed to remove recovery secret", e),
+                },
+                Some(false) => recovery_page(account, true, Some("The recovery secret was not removed.")),
+            },
+            _ => return Response::not_found("".to_owned()),
+        };
+        let secret = match RecoverySecret::new(&secret) {
+            Ok(secret) => secret,
+            Err(e) => {
+                eprintln!("Failed to hash recovery secret: {}", e);
+                return Response::temporary_failure("Failed to hash recovery secret".to_owned());
+            }
+        };
+        match storage.set_recovery_secret(account, Some(&secret)) {
+            Ok(()) => recovery_page(account, true, Some("The recovery secret is set. Keep it somewhere safe, along with the account number.")),
+            Err(e) => storage_failure("Failed to set recovery secret", e),
+        }
+    }
+
+    /// Lets the certificate `fingerprint` claim an account whose recovery secret it knows.
+    /// Attempts are limited, so that secrets cannot be guessed.
+    fn recover_account(&self, storage: &mut Storage, fingerprint: &[u8], path_segments: &[&str], query: Option<String>) -> Response {
+        let game = &self.config.game;
+        let id = match *path_segments {
+            ["account", "recover"] => return match query.map(|entered| entered.trim().parse::<i32>()) {
+                Some(Ok(id)) => Response::redirect_temporary(format!("/account/recover/{}", id)),
+                Some(Err(_)) => Response::input("That is not a number. Number of the account to recover".to_owned()),
+                None => Response::input("Number of the account to recover".to_owned()),
+            },
+            ["account", "recover", id] => match id.parse::<i32>() {
+                Ok(id) => id,
+                Err(_) => return Response::not_found("There is no such account".to_owned()),
+            },
+            _ => return Response::not_found("".to_owned()),
+        };
+        let entered = match query {
+            Some(entered) => entered,
+            None => return Response::sensitive_input(format!("Recovery secret of account {}", id)),
+        };
+        let window = Duration::from_secs(game.recovery_window_minutes * 60);
+        let secret = match storage.attempt_recovery(id, game.recovery_attempts as usize, window) {
+            Ok(Ok(secret)) => secret,
+            Ok(Err(wait)) => return Response::slow_down(wait),
+            Err(e) => return storage_failure("Failed to check recovery secret", e),
+        };
+        let matches = match secret.map(|secret| secret.matches(&entered)) {
+            Some(Ok(matches)) => matches,
+            Some(Err(e)) => {
+                eprintln!("Failed to hash recovery secret: {}", e);
+                return Response::temporary_failure("Failed to check recovery secret".to_owned());
+            }
+            None => false,
+        };
+        if !matches {
+            return Response::success(
+                MediaType::gemini(Some(Language::english())),
+                "### Recovery\r\nThat account number or recovery secret is wrong.\r\n\
+                    => /account/recover Try again\r\n=> /adventure Back".to_owned(),
+            );
+        }
+        let account = match storage.recover_account(fingerprint, id) {
+            Ok(account) => account,
+            Err(e) => return storage_failure("Failed to recover account", e),
+        };
+        match storage.list_certificates(&account) {
+            Ok(certificates) => certificates_page(&certificates, fingerprint,
+                Some(&format!("This certificate now plays account {}. Revoke the certificates you lost.", account.id))),
+            Err(e) => storage_failure("Failed to get certificates", e),
+        }
+    }
+
+    /// Everything kept about the account and its characters, as a document to download.
+    fn export_account(&self, storage: &mut Storage, account: &Account, path_segments: &[&str]) -> Response {
+        let media_type = match *path_segments {
+            ["account", "export"] => return Response::success(
+                MediaType::gemini(Some(Language::english())),
+                "### Export your data\r\nA copy of your account, its certificates and your characters, \
+                    with their stats, inventory, quests and history.\r\n\
+                    => /account/export/namushul.json 📦 As JSON\r\n=> /account/export/namushul.txt 📄 As text\r\n\
+                    => /account Back".to_owned(),
+            ),
+            ["account", "export", "namushul.json"] => MediaType::json(),
+            ["account", "export", "namushul.txt"] => MediaType::plain(),
+            _ => return Response::not_found("".to_owned()),
+        };
+        let export = match gather_export(storage, account) {
+            Ok(export) => export,
+            Err(e) => return storage_failure("Failed to export account", e),
+        };
+        let body = match *path_segments {
+            ["account", "export", "namushul.json"] => export.to_json(),
+            _ => export.to_text(),
+        };
+        Response::success(media_type, body)
+    }
+
+    /// Deletes the account along with its certificates and characters, once the player confirms it.
+    fn delete_account(&self, storage: &mut Storage, account: &Account, query: Option<String>) -> Response {
+        match confirmed(query.as_deref(), "DELETE") {
+            None => match storage.list_characters(account) {
+                Ok(characters) => Response::input(format!(
+                    "Deleting account {} removes its {} character(s) and cannot be undone. Type DELETE to confirm",
+                    account.id, characters.len())),
+                Err(e) => storage_failure("Failed to get characters", e),
+            },
+            Some(true) => match storage.delete_account(account) {
+                Ok(()) => Response::success(
+                    MediaType::gemini(Some(Language::english())),
+                    "### Farewell\r\nYour account and everything about it is deleted.\r\n=> / Home".to_owned(),
+                ),
+                Err(e) => storage_failure("Failed to delete account", e),
+            },
+            Some(false) => Response::success(
+                MediaType::gemini(Some(Language::english())),
+                "### Delete your account\r\nThe account was not deleted.\r\n=> /account Back".to_owned(),
+            ),
+        }
+    }
+
+    fn show_character(&self, storage: &mut Storage, user: &User, note: Option<&str>) -> Response {
+        let inventory = match storage.get_inventory(user) {
+            Ok(inventory) => inventory,
+            Err(e) => return storage_failure("Failed to get inventory", e),
+        };
+        match storage.get_equipment(user) {
+            Ok(equipment) => character_page(&self.config.game, &self.content, user, &inventory, &equipment, note),
+            Err(e) => storage_failure("Failed to get equipment", e),
+        }
+    }
+
+    fn show_inventory(&self, storage: &mut Storage, user: &User, note: &str) -> Response {
+        match storage.get_inventory(user) {
+            Ok(inventory) => inventory_page(&self.config.game, &self.content, user, &inventory, Some(note)),
+            Err(e) => storage_failure("Failed to get inventory", e),
+        }
+    }
+
+    fn show_location(&self, storage: &mut Storage, user: &User, location: &Location, log: &[String]) -> Response {
+        match storage.get_quests(user) {
+            Ok(quests) => location_page(&self.config.game, &self.content, user, location, &quests, log),
+            Err(e) => storage_failure("Failed to get quests", e),
+        }
+    }
+
+    fn show_quest(&self, storage: &mut Storage, user: &User, quest: &Quest, note: Option<&str>) -> Response {
+        let progress = match storage.get_quests(user) {
+            Ok(quests) => quests.into_iter().find(|progress| progress.quest == quest.id),
+            Err(e) => return storage_failure("Failed to get quests", e),
+        };
+        match storage.get_inventory(user) {
+            Ok(inventory) => quest_page(&self.config.game, &self.content, user, quest, progress.as_ref(), &inventory, note),
+            Err(e) => storage_failure("Failed to get inventory", e),
+        }
+    }
+
+    /// The dice for the action the user is taking, whose token they just used up.
+    fn dice(&self, user: &User) -> Dice {
+        self.random.dice(user.id, user.actions)
+    }
+
+    /// Rolls what happens as the user enters or explores `location`, and shows it. `log` tells what
+    /// happened before.
+    fn encounter(&self, storage: &mut Storage, user: User, location: &Location, mut log: Vec<String>) -> Response {
+        let encounter = match location.roll_encounter(&mut self.dice(&user)) {
+            Some(encounter) => encounter,
+            None => return self.show_location(storage, &user, location, &log),
+        };
+        let text = encounter.text.clone();
+        match encounter.happening() {
+            Happening::Monster(id) => {
+                let monster = self.content.monster(id).expect("validated content only has encounters with monsters that exist");
+                log.push(text.unwrap_or_else(|| format!("A {} appears!", monster.name)));
+                match storage.start_encounter(&user, monster.id.clone(), monster.health) {
+                    Ok(encounter) => fight_page(&self.config.game, &user, monster, &encounter, &log),
+                    Err(storage::Error::UniqueViolation(constraint)) if constraint == "encounters_pkey" =>
+                        Response::redirect_temporary("/adventure".to_owned()),
+                    Err(e) => storage_failure("Failed to start fight", e),
+                }
+            }
+            Happening::Item(id, quantity) => {
+                log.push(text.unwrap_or_else(|| "Something catches your eye.".to_owned()));
+                match self.pick_up(storage, user, &[(id.to_owned(), quantity)]) {
+                    Ok((user, lines)) => {
+                        log.extend(lines);
+                        self.show_location(storage, &user, location, &log)
+                    }
+                    Err(e) => storage_failure("Failed to pick up item", e),
+                }
+            }
+            Happening::Npc(id) => {
+                let npc = self.content.npc(id).expect("validated content only has encounters with characters that exist");
+                log.push(text.unwrap_or_else(|| format!("You run into {}.", npc.name)));
+                let start = npc.node(&npc.start).expect("validated characters have a start node");
+                match storage.set_conversation(&user, &npc.id, &start.id) {
+                    Ok(()) => self.show_conversation(storage, &user, npc, start, &log),
+                    Err(e) => storage_failure("Failed to start conversation", e),
+                }
+            }
+            Happening::Nothing => {
+                log.push(text.unwrap_or_else(|| "You see nothing of interest.".to_owned()));
+                self.show_location(storage, &user, location, &log)
+            }
         }
+    }
+
+    /// Shows the conversation with `npc` at `node`, offering the choices the user may make.
+    fn show_conversation(&self, storage: &mut Storage, user: &User, npc: &Npc, node: &Node, log: &[String]) -> Response {
+        let quests = match storage.get_quests(user) {
+            Ok(quests) => quests,
+            Err(e) => return storage_failure("Failed to get quests", e),
+        };
+        let inventory = match storage.get_inventory(user) {
+            Ok(inventory) => inventory,
+            Err(e) => return storage_failure("Failed to get inventory", e),
+        };
+        let choices = node.choices.iter().enumerate()
+            .filter(|(_, choice)| choice.conditions.met(user.level, &inventory, |id| self.quest_state(id, user, &quests, &inventory)))
+            .collect::<Vec<_>>();
+        conversation_page(&self.config.game, user, npc, node, &choices, log)
+    }
+
+    fn quest_state(&self, id: &str, user: &User, quests: &[QuestProgress], inventory: &Inventory) -> QuestState {
+        self.content.quest(id).map_or(QuestState::Unavailable, |quest| quest.state(user.level, quests, inventory))
+    }
 
+    /// Counts `event` towards the open quests of the user. Returns a line about each objective
+    /// that got further.
+    fn advance_quests(&self, storage: &mut Storage, user: &User, event: Event) -> Result<Vec<String>, storage::Error> {
+        let mut lines = vec![];
+        for progress in storage.get_quests(user)?.into_iter().filter(|progress| !progress.completed) {
+            let quest = match self.content.quest(&progress.quest) {
+                Some(quest) => quest,
+                None => continue,
+            };
+            let mut counts = progress.progress.clone();
+            if !quest.record(&mut counts, event) {
+                continue;
+            }
+            storage.update_quest_progress(user, &quest.id, &counts)?;
+            for (i, objective) in quest.objectives.iter().enumerate() {
+                if counts[i] != progress.progress.get(i).copied().unwrap_or(0) {
+                    lines.push(format!("📜 {}: {}", quest.name, objective_text(&self.content, objective, counts[i])));
+                }
+            }
+        }
+        Ok(lines)
+    }
+
+    fn show_shop(&self, storage: &mut Storage, user: &User, shop: &Shop, note: Option<&str>) -> Response {
+        let supplies = match storage.get_supplies(&shop.id) {
+            Ok(supplies) => supplies,
+            Err(e) => return storage_failure("Failed to get stock", e),
+        };
+        match storage.get_inventory(user) {
+            Ok(inventory) => shop_page(&self.config.game, &self.content, user, shop, &supplies, &inventory, note),
+            Err(e) => storage_failure("Failed to get inventory", e),
+        }
+    }
+
+    /// Puts `loot` into the user's inventory, as far as it fits. Returns a line about each item.
+    fn pick_up(&self, storage: &mut Storage, user: User, loot: &[(String, i32)]) -> Result<(User, Vec<String>), storage::Error> {
+        let content = &self.content;
+        let capacity = self.config.game.inventory_slots;
+        storage.update_inventory(user, self.config.game.health_regeneration(), |_, inventory, _| {
+            loot.iter().filter_map(|(id, quantity)| {
+                let item = content.item(id)?;
+                let added = inventory.add(&content.items, item, *quantity, capacity);
+                Some(match added {
+                    0 => format!("Your bags are full, so you leave the {} behind.", item.name),
+                    added if added < *quantity => format!("{} × {}, leaving the rest behind as your bags are full.", item.name, added),
+                    added => format!("{} × {}", item.name, added),
+                })
+            }).collect()
+        })
+    }
+
+    /// Carries a knocked out character to the last inn they visited, or to the start of the world
+    /// when there is none, and records `description` in their history.
+    fn knock_out(&self, storage: &mut Storage, user: User, description: &str, log: &[String]) -> Response {
+        let world = &self.content.world;
+        let game = &self.config.game;
+        let inn = user.last_inn.as_deref()
+            .and_then(|id| world.location(id))
+            .filter(|location| location.is_inn())
+            .unwrap_or_else(|| world.location_or_start(&world.start));
+        let result = storage.knock_out(user, inn.id.clone(), description, |user| {
+            user.health = max(1, user.max_health * game.knockout_health_percent / 100);
+            let lost = progression::knockout_xp_loss(game, user);
+            let gold_lost = user.gold * game.knockout_gold_percent / 100;
+            user.xp -= lost;
+            user.gold -= gold_lost;
+            (lost, gold_lost)
+        });
+        let (user, (lost, gold_lost)) = match result {
+            Ok(result) => result,
+            Err(e) => return storage_failure("Failed to carry you to the inn", e),
+        };
+        let mut log = log.to_vec();
+        if lost > 0 {
+            log.push(format!("You lose {} XP.", lost));
+        }
+        if gold_lost > 0 {
+            log.push(format!("You lose {} gold.", gold_lost));
+        }
+        wake_up_page(game, &user, inn, &log)
+    }
+
+    pub fn handle_request(&self, request: Request) -> Response {
         let path_segments = match request.url.path_segments() {
             None => vec![],
             Some(segments) => segments.collect::<Vec<_>>()
         };
+        if has_secret_query(&path_segments) && request.url.query().is_some() {
+            let mut url = request.url.clone();
+            url.set_query(Some("redacted"));
+            eprintln!("Request: {}", url);
+            eprintln!("Request-path: {}", url.path());
+        } else {
+            eprintln!("Request: {}", request.url);
+            eprintln!("Request-path: {}", request.url.path());
+            eprintln!("Request-query: {:?}", request.url.query_pairs().map(|(k, v)| { format!("{}: {}", k, v) }).collect::<Vec<String>>());
+            eprintln!("Request-query: {:?}", request.query);
+        }
+
+        if self.config.transport.simulate_latency {
+            sleep(Duration::from_secs(1));
+        }
+
+        let world = &self.content.world;
+        let game = &self.config.game;
         eprintln!("{:?}", path_segments);
 
-        let mut storage = match Storage::new() {
+        let mut storage = match Storage::new(&self.config.storage) {
             Ok(storage) => storage,
-            Err(_) => return Response::temporary_failure("Failed to connect to database".into()),
+            Err(e) => return storage_failure("Failed to connect to database", e),
         };
 
         let user = match &request.peer_fingerprint {
             Some(fingerprint) => {
-                match storage.get_user(fingerprint) {
+                match storage.get_user(fingerprint, game.health_regeneration()) {
                     Ok(user) => Some(user),
                     Err(storage::Error::NotFound) => None,
-                    Err(_) => return Response::temporary_failure("Failed to get user".into()),
+                    Err(e) => return storage_failure("Failed to get user", e),
                 }
             }
             None => None
@@ -118,7 +1231,7 @@ impl Application {
             ["about"] => {
                 let user_count = match storage.count_users() {
                     Ok(count) => count,
-                    Err(_) => return Response::temporary_failure("Failed to count users".into()),
+                    Err(e) => return storage_failure("Failed to count users", e),
                 };
                 let fields = [
                     format!("👥 Users: {}", user_count),
@@ -140,74 +1253,580 @@ impl Application {
             }
         };
 
+        let account = match storage.get_account(fingerprint) {
+            Ok(account) => account,
+            Err(e) => return storage_failure("Failed to get account", e),
+        };
+        match path_segments[..] {
+            ["adventure", "create", ..] => return self.create_character(&mut storage, &account, &path_segments, request.query),
+            ["adventure", "characters", ..] => return self.manage_characters(&mut storage, &account, &path_segments, request.query),
+            ["account", "certificates", ..] =>
+                return self.manage_certificates(&mut storage, &account, fingerprint, &path_segments, request.query),
+            ["account", "recovery", ..] => return self.manage_recovery(&mut storage, &account, &path_segments, request.query),
+            ["account", "recover", ..] => return self.recover_account(&mut storage, fingerprint, &path_segments, request.query),
+            ["account", "export", ..] => return self.export_account(&mut storage, &account, &path_segments),
+            ["account", "delete"] => return self.delete_account(&mut storage, &account, request.query),
+            _ => {}
+        }
+
         eprintln!("User: {:?}", user);
-        let user = match user {
+        let mut user = match user {
             Some(user) => user,
-            None => {
-                match request.query.clone() {
-                    None => return Response::input("Choose a name for your character".to_owned()),
-                    Some(name) => {
-                        match storage.create_user(fingerprint, name) {
-                            Ok(user) => user,
-                            Err(_) => return Response::temporary_failure("Failed to create user".into())
-                        }
-                    }
-                }
-            }
+            // Accounts with characters, but none to play, such as after deleting one, pick one.
+            None => return match storage.list_characters(&account) {
+                Ok(characters) if characters.is_empty() => self.create_character(&mut storage, &account, &path_segments, request.query),
+                Ok(characters) => characters_page(game, &self.content, &account, &characters, None),
+                Err(e) => storage_failure("Failed to get characters", e),
+            },
         };
 
+        // Actions that change the game carry a token that only works once, so that reloading a page
+        // or prefetching its links does not do them again. Whatever they would have done has
+        // happened already, so the character is just shown where they are.
+        let (token, path_segments) = split_action_token(path_segments);
+        if changes_state(&path_segments, request.query.as_deref()) {
+            let token = match token {
+                Some(token) => token,
+                None => return Response::redirect_temporary("/adventure".to_owned()),
+            };
+            user = match storage.use_action_token(user, token, game.health_regeneration()) {
+                Ok(user) => user,
+                Err(storage::Error::NotFound) => return Response::redirect_temporary("/adventure".to_owned()),
+                Err(e) => return storage_failure("Failed to use action token", e),
+            };
+        }
+
+        let location = world.location_or_start(&user.location);
+        let encounter = match storage.get_encounter(&user) {
+            Ok(encounter) => encounter,
+            Err(e) => return storage_failure("Failed to get fight", e),
+        };
+        let fight = match encounter {
+            Some(encounter) => match self.content.monster(&encounter.monster) {
+                Some(monster) => Some((monster, encounter)),
+                // The monster has been removed from the content since, so the fight is called off.
+                None => {
+                    user = match storage.finish_round(user, game.health_regeneration(), None, |_| ()) {
+                        Ok((user, ())) => user,
+                        Err(e) => return storage_failure("Failed to end fight", e),
+                    };
+                    None
+                }
+            },
+            None => None,
+        };
+        // Characters left without health outside of a fight, such as those from before knockouts
+        // existed, are carried to the inn like any other knocked out character.
+        if user.health <= 0 && fight.is_none() && path_segments.first() == Some(&"adventure") {
+            let description = format!("Collapsed from exhaustion in {}.", location.name);
+            return self.knock_out(&mut storage, user, &description, &[]);
+        }
         match path_segments[..] {
-            ["adventure"] => {
-                status_page(user)
+            ["adventure"] | ["adventure", "fight"] if fight.is_some() => {
+                let (monster, encounter) = fight.unwrap();
+                fight_page(game, &user, monster, &encounter, &[])
             }
+            ["adventure"] => self.show_location(&mut storage, &user, location, &[]),
             ["adventure", "fight"] => {
-                let health = user.health - 1;
-                if health < 0 { return Response::redirect_temporary("/adventure".to_owned()); }
-                match storage.update_health(user, health) {
-                    Ok(_user) => Response::redirect_temporary("/adventure".to_owned()),
-                    Err(_) => Response::temporary_failure("Failed to update user".into())
+                if !location.actions.contains(&Action::Fight) {
+                    return Response::bad_request("There is nothing to fight here".to_owned());
+                }
+                let monster = location.monsters.choose(&mut self.dice(&user))
+                    .and_then(|id| self.content.monster(id))
+                    .expect("validated content only has fights where there are monsters");
+                match storage.start_encounter(&user, monster.id.clone(), monster.health) {
+                    Ok(encounter) => fight_page(game, &user, monster, &encounter, &[]),
+                    // Another request started a fight first.
+                    Err(storage::Error::UniqueViolation(constraint)) if constraint == "encounters_pkey" =>
+                        Response::redirect_temporary("/adventure".to_owned()),
+                    Err(e) => storage_failure("Failed to start fight", e),
+                }
+            }
+            ["adventure", "fight", character_move] => {
+                let character_move = match Move::from_segment(character_move) {
+                    Some(character_move) => character_move,
+                    None => return Response::not_found("There is no such move".to_owned()),
+                };
+                let (monster, encounter) = match fight {
+                    Some(fight) => fight,
+                    None => return Response::redirect_temporary("/adventure".to_owned()),
+                };
+                let equipment = match storage.get_equipment(&user) {
+                    Ok(equipment) => equipment,
+                    Err(e) => return storage_failure("Failed to get equipment", e),
+                };
+                let bonus = equipment.bonus(&self.content.items);
+                let stats = character_stats(game, &user, bonus);
+                let mut round = combat::play_round(stats, user.health, monster, encounter.monster_health, character_move, &mut self.dice(&user));
+                if round.outcome == Outcome::Defeat {
+                    let description = format!("Knocked out by the {} in {}.", monster.name, location.name);
+                    return self.knock_out(&mut storage, user, &description, &round.log);
+                }
+                let next = Encounter { monster_health: round.monster_health, round: encounter.round + 1, ..encounter };
+                let ongoing = round.outcome == Outcome::Ongoing;
+                // The round is applied to the user as stored, which another request may have changed since.
+                let health_change = round.health - user.health;
+                let found = match round.outcome {
+                    Outcome::Victory { gold, .. } => Some(gold),
+                    _ => None,
+                };
+                let result = storage.finish_round(user, game.health_regeneration(), if ongoing { Some(&next) } else { None }, |user| {
+                    user.health = max(0, min(user.max_health, user.health + health_change));
+                    let level = user.level;
+                    if let Some(gold) = found {
+                        user.gold += gold;
+                        *user = progression::award_xp(game, user.clone(), monster.xp);
+                    }
+                    user.level > level
+                });
+                if let Some(gold) = found {
+                    if gold > 0 {
+                        round.log.push(format!("You find {} gold.", gold));
+                    }
+                    round.log.push(format!("You gain {} XP.", monster.xp));
+                }
+                if let Ok((user, true)) = &result {
+                    let stats = character_stats(game, user, bonus);
+                    round.log.push(format!("🎉 You reached level {}! You now have {} max HP, {} attack and {} defense.",
+                        user.level, user.max_health, stats.attack, stats.defense));
+                }
+                match result {
+                    Ok((user, _)) if ongoing => fight_page(game, &user, monster, &next, &round.log),
+                    Ok((user, _)) => {
+                        let loot = match &round.outcome {
+                            Outcome::Victory { items, .. } => items.clone(),
+                            _ => vec![],
+                        };
+                        if let Outcome::Victory { .. } = round.outcome {
+                            match self.advance_quests(&mut storage, &user, Event::Kill(&monster.id)) {
+                                Ok(lines) => round.log.extend(lines),
+                                Err(e) => return storage_failure("Failed to update quests", e),
+                            }
+                        }
+                        match self.pick_up(&mut storage, user, &loot) {
+                            Ok((user, lines)) => fight_over_page(game, &user, monster, &round, &lines),
+                            Err(e) => storage_failure("Failed to pick up loot", e),
+                        }
+                    }
+                    Err(e) => storage_failure("Failed to update fight", e),
                 }
             }
+            // Nobody gets to rest or walk away in the middle of a fight.
+            ["adventure", "rest"] | ["adventure", "go", _] | ["adventure", "explore"] if fight.is_some() =>
+                Response::redirect_temporary("/adventure".to_owned()),
             ["adventure", "rest"] => {
+                if !location.is_inn() {
+                    return Response::bad_request("There is no inn here".to_owned());
+                }
+                if user.health >= user.max_health {
+                    return rest_page(game, &user, location, "You are already well rested.");
+                }
                 let health = user.max_health;
-                match storage.update_health(user, health) {
-                    Ok(_user) => Response::redirect_temporary("/adventure".to_owned()),
-                    Err(_) => Response::temporary_failure("Failed to update user".into())
+                let price = game.rest_price;
+                match storage.rest(user.clone(), health, price) {
+                    Ok(user) => rest_page(game, &user, location, &format!("You pay {} gold for a room and sleep soundly.", price)),
+                    Err(storage::Error::CheckViolation(constraint)) if constraint == "users_gold_check" =>
+                        rest_page(game, &user, location, &format!("A room costs {} gold, but you only have {}. \
+                            The innkeeper shows you the door.", price, user.gold)),
+                    Err(e) => storage_failure("Failed to update user", e)
+                }
+            }
+            ["adventure", "explore"] => {
+                if !location.actions.contains(&Action::Explore) {
+                    return Response::bad_request("There is nothing to explore here".to_owned());
+                }
+                self.encounter(&mut storage, user, location, vec![])
+            }
+            ["adventure", "go", destination] => {
+                if location.id == destination {
+                    return self.show_location(&mut storage, &user, location, &[]);
+                }
+                let destination = match world.location(destination) {
+                    Some(destination) => destination,
+                    None => return Response::not_found("There is no such place".to_owned()),
+                };
+                let exit = match location.exit_to(&destination.id) {
+                    Some(exit) => exit,
+                    None => return Response::bad_request(format!("You cannot get to {} from here", destination.name)),
+                };
+                let items = match storage.get_inventory(&user) {
+                    Ok(inventory) => inventory.item_ids(),
+                    Err(e) => return storage_failure("Failed to get inventory", e),
+                };
+                let traveller = Traveller { level: user.level, health: user.health, items };
+                if let Err(refusal) = exit.check(&traveller) {
+                    return refusal_page(&self.content, destination, refusal);
+                }
+                match storage.travel(user, game.health_regeneration(), destination.id.clone(), exit.cost.health, destination.is_inn()) {
+                    Ok(user) => match self.advance_quests(&mut storage, &user, Event::Reach(&destination.id)) {
+                        Ok(log) => self.encounter(&mut storage, user, destination, log),
+                        Err(e) => storage_failure("Failed to update quests", e),
+                    },
+                    Err(e) => storage_failure("Failed to update user", e)
+                }
+            }
+            ["adventure", "inventory"] => {
+                match storage.get_inventory(&user) {
+                    Ok(inventory) => inventory_page(game, &self.content, &user, &inventory, None),
+                    Err(e) => storage_failure("Failed to get inventory", e),
+                }
+            }
+            ["adventure", "character"] => self.show_character(&mut storage, &user, None),
+            // Changing clothes in the middle of a fight does not end well.
+            ["adventure", "character", _, _] if fight.is_some() =>
+                Response::redirect_temporary("/adventure".to_owned()),
+            ["adventure", "character", "equip", id] => {
+                let item = match self.content.item(id) {
+                    Some(item) => item,
+                    None => return Response::not_found("There is no such item".to_owned()),
+                };
+                let content = &self.content;
+                let result = storage.update_inventory(user, game.health_regeneration(), |user, inventory, equipment| {
+                    match equipment.equip(&content.items, inventory, item, game.inventory_slots) {
+                        Ok(()) => {
+                            progression::update_max_health(game, user, equipment.bonus(&content.items).health);
+                            format!("You put on the {}.", item.name)
+                        }
+                        Err(e) => equip_error_text(e, &item.name),
+                    }
+                });
+                match result {
+                    Ok((user, note)) => self.show_character(&mut storage, &user, Some(&note)),
+                    Err(e) => storage_failure("Failed to equip item", e),
+                }
+            }
+            ["adventure", "character", "unequip", slot] => {
+                let slot = match Slot::from_segment(slot) {
+                    Some(slot) => slot,
+                    None => return Response::not_found("There is no such slot".to_owned()),
+                };
+                let content = &self.content;
+                let result = storage.update_inventory(user, game.health_regeneration(), |user, inventory, equipment| {
+                    match equipment.unequip(&content.items, inventory, slot, game.inventory_slots) {
+                        Ok(id) => {
+                            // Taking off what raised maximum health may leave the character with less health.
+                            progression::update_max_health(game, user, equipment.bonus(&content.items).health);
+                            format!("You take off the {}.", content.item_name(&id))
+                        }
+                        Err(e) => equip_error_text(e, slot.label()),
+                    }
+                });
+                match result {
+                    Ok((user, note)) => self.show_character(&mut storage, &user, Some(&note)),
+                    Err(e) => storage_failure("Failed to unequip item", e),
                 }
             }
-            ["adventure", "bastow"] => {
-                if user.location_id == locations::BASTOW {
-                    return status_page(user);
+            ["adventure", "inventory", "use", _] if fight.is_some() =>
+                Response::redirect_temporary("/adventure".to_owned()),
+            ["adventure", "inventory", "use", id] => {
+                let item = match self.content.item(id) {
+                    Some(item) if item.kind == ItemKind::Consumable => item,
+                    Some(_) => return Response::bad_request("That cannot be used".to_owned()),
+                    None => return Response::not_found("There is no such item".to_owned()),
+                };
+                let result = storage.update_inventory(user, game.health_regeneration(), |user, inventory, _| {
+                    if user.health >= user.max_health {
+                        return "You are already at full health.".to_owned();
+                    }
+                    if !inventory.remove(&item.id, 1) {
+                        return format!("You have no {} left.", item.name);
+                    }
+                    let healed = min(item.heal, user.max_health - user.health);
+                    user.health += healed;
+                    format!("You use a {} and recover {} HP.", item.name, healed)
+                });
+                match result {
+                    Ok((user, note)) => self.show_inventory(&mut storage, &user, &note),
+                    Err(e) => storage_failure("Failed to use item", e),
                 }
-                if user.location_id != locations::BASTOW_WOODLANDS {
-                    return Response::bad_request("Invalid destination".to_owned());
+            }
+            ["adventure", "inventory", "drop", id] => {
+                let name = self.content.item_name(id);
+                let result = storage.update_inventory(user, game.health_regeneration(), |_, inventory, _| {
+                    if inventory.remove(id, 1) {
+                        format!("You drop a {}.", name)
+                    } else {
+                        format!("You have no {} to drop.", name)
+                    }
+                });
+                match result {
+                    Ok((user, note)) => self.show_inventory(&mut storage, &user, &note),
+                    Err(e) => storage_failure("Failed to drop item", e),
                 }
-                match storage.update_location_id(user, locations::BASTOW) {
-                    Ok(user) => status_page(user),
-                    Err(_) => Response::temporary_failure("Failed to update user".into())
+            }
+            ["adventure", "quests"] => {
+                let quests = match storage.get_quests(&user) {
+                    Ok(quests) => quests,
+                    Err(e) => return storage_failure("Failed to get quests", e),
+                };
+                match storage.get_inventory(&user) {
+                    Ok(inventory) => quest_log_page(game, &self.content, &user, &quests, &inventory),
+                    Err(e) => storage_failure("Failed to get inventory", e),
                 }
             }
-            ["adventure", "bastow-woodlands"] => {
-                if user.location_id == locations::BASTOW_WOODLANDS {
-                    return status_page(user);
+            ["adventure", "quests", id, ..] if self.content.quest(id).is_none() =>
+                Response::not_found("There is no such quest".to_owned()),
+            ["adventure", "quests", id] => self.show_quest(&mut storage, &user, self.content.quest(id).unwrap(), None),
+            ["adventure", "quests", _, _] if fight.is_some() =>
+                Response::redirect_temporary("/adventure".to_owned()),
+            ["adventure", "quests", id, _] if self.content.quest(id).unwrap().location != location.id =>
+                Response::bad_request("The quest giver is not here".to_owned()),
+            ["adventure", "quests", id, "accept"] => {
+                let quest = self.content.quest(id).unwrap();
+                let quests = match storage.get_quests(&user) {
+                    Ok(quests) => quests,
+                    Err(e) => return storage_failure("Failed to get quests", e),
+                };
+                if quests.iter().any(|progress| progress.quest == quest.id) {
+                    return self.show_quest(&mut storage, &user, quest, Some("You have already taken on this quest."));
                 }
-                match storage.update_location_id(user, locations::BASTOW_WOODLANDS) {
-                    Ok(user) => status_page(user),
-                    Err(_) => Response::temporary_failure("Failed to update user".into())
+                let completed = quests.iter().filter(|progress| progress.completed).map(|progress| progress.quest.as_str()).collect::<Vec<_>>();
+                if !quest.available(user.level, &completed) {
+                    return Response::bad_request("You cannot take on this quest yet".to_owned());
+                }
+                match storage.start_quest(&user, &quest.id, &vec![0; quest.objectives.len()]) {
+                    Ok(()) => self.show_quest(&mut storage, &user, quest, Some(&format!("You accept the quest. {} nods.", quest.giver))),
+                    Err(e) => storage_failure("Failed to start quest", e),
                 }
             }
-            ["account"] =>
+            ["adventure", "quests", id, "complete"] => {
+                let quest = self.content.quest(id).unwrap();
+                let counts = match storage.get_quests(&user) {
+                    Ok(quests) => quests.into_iter().find(|progress| progress.quest == quest.id).map(|progress| progress.progress),
+                    Err(e) => return storage_failure("Failed to get quests", e),
+                };
+                let counts = counts.unwrap_or_default();
+                let content = &self.content;
+                let description = format!("Completed the quest {}.", quest.name);
+                let result = storage.complete_quest(user, game.health_regeneration(), &quest.id, &description, |user, inventory, _| {
+                    if !quest.done(&counts, inventory) {
+                        return Err("You have not done everything yet.".to_owned());
+                    }
+                    let mut changed = inventory.clone();
+                    for objective in &quest.objectives {
+                        if let Objective::Deliver { item, count } = objective {
+                            changed.remove(item, *count);
+                        }
+                    }
+                    let mut lines = vec![format!("{} says: “{}”", quest.giver, quest.completion)];
+                    for reward in &quest.rewards.items {
+                        let item = match content.item(&reward.item) {
+                            Some(item) => item,
+                            None => continue,
+                        };
+                        if changed.add(&content.items, item, reward.quantity, game.inventory_slots) < reward.quantity {
+                            return Err("Your bags are too full to take the reward. Make some room first.".to_owned());
+                        }
+                        lines.push(format!("You receive {} × {}.", item.name, reward.quantity));
+                    }
+                    *inventory = changed;
+                    let rewards = &quest.rewards;
+                    if rewards.gold > 0 {
+                        user.gold += rewards.gold;
+                        lines.push(format!("You receive {} gold.", rewards.gold));
+                    }
+                    if rewards.xp > 0 {
+                        let level = user.level;
+                        *user = progression::award_xp(game, user.clone(), rewards.xp);
+                        lines.push(format!("You gain {} XP.", rewards.xp));
+                        if user.level > level {
+                            lines.push(format!("🎉 You reached level {}!", user.level));
+                        }
+                    }
+                    Ok(lines)
+                });
+                match result {
+                    Ok((user, Ok(lines))) => self.show_quest(&mut storage, &user, quest, Some(&lines.join("\r\n"))),
+                    Ok((user, Err(note))) => self.show_quest(&mut storage, &user, quest, Some(&note)),
+                    Err(storage::Error::NotFound) => Response::bad_request("You are not on this quest".to_owned()),
+                    Err(e) => storage_failure("Failed to complete quest", e),
+                }
+            }
+            // Conversations wait until the fight is over.
+            ["adventure", "talk", ..] if fight.is_some() =>
+                Response::redirect_temporary("/adventure".to_owned()),
+            ["adventure", "talk", id, ..] if self.content.npc(id)
+                .filter(|npc| npc.location.as_deref() == Some(&location.id) || location.has_wanderer(&npc.id))
+                .is_none() =>
+                Response::not_found("There is nobody like that here".to_owned()),
+            ["adventure", "talk", id] => {
+                let npc = self.content.npc(id).unwrap();
+                // Pick up the conversation where it was left, if it was with the same character.
+                let node = match storage.get_conversation(&user) {
+                    Ok(Some((talking_to, node))) if talking_to == npc.id => npc.node(&node),
+                    Ok(_) => None,
+                    Err(e) => return storage_failure("Failed to get conversation", e),
+                };
+                let node = match node {
+                    Some(node) => node,
+                    None => {
+                        let start = npc.node(&npc.start).unwrap();
+                        if let Err(e) = storage.set_conversation(&user, &npc.id, &start.id) {
+                            return storage_failure("Failed to start conversation", e);
+                        }
+                        start
+                    }
+                };
+                self.show_conversation(&mut storage, &user, npc, node, &[])
+            }
+            ["adventure", "talk", id, choice] => {
+                let npc = self.content.npc(id).unwrap();
+                let node = match storage.get_conversation(&user) {
+                    Ok(Some((talking_to, node))) if talking_to == npc.id => npc.node(&node),
+                    Ok(_) => None,
+                    Err(e) => return storage_failure("Failed to get conversation", e),
+                };
+                let node = match node {
+                    Some(node) => node,
+                    None => return Response::redirect_temporary(format!("/adventure/talk/{}", npc.id)),
+                };
+                let quests = match storage.get_quests(&user) {
+                    Ok(quests) => quests,
+                    Err(e) => return storage_failure("Failed to get quests", e),
+                };
+                let inventory = match storage.get_inventory(&user) {
+                    Ok(inventory) => inventory,
+                    Err(e) => return storage_failure("Failed to get inventory", e),
+                };
+                let choice = match choice.parse::<usize>().ok().and_then(|i| node.choices.get(i)) {
+                    Some(choice) if choice.conditions.met(user.level, &inventory, |id| self.quest_state(id, &user, &quests, &inventory)) => choice,
+                    _ => return Response::bad_request("You cannot say that".to_owned()),
+                };
+                let mut log = vec![];
+                if let Some(quest) = choice.effects.start_quest.as_deref().and_then(|id| self.content.quest(id)) {
+                    if quest.state(user.level, &quests, &inventory) == QuestState::Available {
+                        if let Err(e) = storage.start_quest(&user, &quest.id, &vec![0; quest.objectives.len()]) {
+                            return storage_failure("Failed to start quest", e);
+                        }
+                        log.push(format!("📜 New quest: {}", quest.name));
+                    }
+                }
+                let mut user = user;
+                if let Some((gift, item)) = choice.effects.give_item.as_ref().and_then(|gift| Some((gift, self.content.item(&gift.item)?))) {
+                    let result = storage.update_inventory(user, game.health_regeneration(), |_, inventory, _| {
+                        match inventory.add(&self.content.items, item, gift.quantity, game.inventory_slots) {
+                            0 => format!("{} has a {} for you, but your bags are full.", npc.name, item.name),
+                            added => format!("{} gives you {} × {}.", npc.name, item.name, added),
+                        }
+                    });
+                    match result {
+                        Ok((changed, line)) => {
+                            user = changed;
+                            log.push(line);
+                        }
+                        Err(e) => return storage_failure("Failed to give item", e),
+                    }
+                }
+                let next = choice.next.as_deref().and_then(|next| npc.node(next));
+                let result = match next {
+                    Some(next) => storage.set_conversation(&user, &npc.id, &next.id),
+                    None => storage.end_conversation(&user),
+                };
+                if let Err(e) = result {
+                    return storage_failure("Failed to update conversation", e);
+                }
+                match next {
+                    Some(next) => self.show_conversation(&mut storage, &user, npc, next, &log),
+                    None => self.show_location(&mut storage, &user, location, &log),
+                }
+            }
+            // Shopkeepers do not serve customers who are being chased by monsters.
+            ["adventure", "shop", ..] if fight.is_some() =>
+                Response::redirect_temporary("/adventure".to_owned()),
+            ["adventure", "shop", id, ..] if self.content.shop(id).filter(|shop| shop.location == location.id).is_none() =>
+                Response::not_found("There is no such shop here".to_owned()),
+            ["adventure", "shop", id] => self.show_shop(&mut storage, &user, self.content.shop(id).unwrap(), None),
+            ["adventure", "shop", id, "buy", item] => {
+                let shop = self.content.shop(id).unwrap();
+                let (stock, item) = match shop.stock(item).zip(self.content.item(item)) {
+                    Some(found) => found,
+                    None => return Response::not_found("The shop does not sell that".to_owned()),
+                };
+                let price = stock.price(item);
+                let quantity = match parse_quantity(request.query.as_deref()) {
+                    Some(quantity) => quantity,
+                    None => return Response::input(format!("How many? A {} costs {} gold", item.name, price)),
+                };
+                let content = &self.content;
+                let note = |result| match result {
+                    Ok(()) => format!("You buy {} × {} for {} gold.", item.name, quantity, price * quantity),
+                    Err(e) => trade_error_text(e, &item.name),
+                };
+                let result = match stock.quantity {
+                    None => storage.update_inventory(user, game.health_regeneration(), |user, inventory, _| {
+                        note(shops::buy(&content.items, user, inventory, item, price, quantity, game.inventory_slots))
+                    }),
+                    Some(full) => storage.trade(user, game.health_regeneration(), &shop.id, &item.id, full, |user, inventory, supply| {
+                        stock.restock(supply, SystemTime::now());
+                        let mut left = *supply;
+                        let result = left.take(quantity)
+                            .and_then(|()| shops::buy(&content.items, user, inventory, item, price, quantity, game.inventory_slots));
+                        if result.is_ok() {
+                            *supply = left;
+                        }
+                        note(result)
+                    }),
+                };
+                match result {
+                    Ok((user, note)) => self.show_shop(&mut storage, &user, shop, Some(&note)),
+                    Err(e) => storage_failure("Failed to buy item", e),
+                }
+            }
+            ["adventure", "shop", id, "sell", item] => {
+                let shop = self.content.shop(id).unwrap();
+                let (item, price) = match self.content.item(item).and_then(|item| Some((item, shop.offer(item)?))) {
+                    Some(found) => found,
+                    None => return Response::bad_request("The shop does not buy that".to_owned()),
+                };
+                let quantity = match parse_quantity(request.query.as_deref()) {
+                    Some(quantity) => quantity,
+                    None => return Response::input(format!("How many? The shop pays {} gold for a {}", price, item.name)),
+                };
+                let result = storage.update_inventory(user, game.health_regeneration(), |user, inventory, _| {
+                    match shops::sell(shop, user, inventory, item, quantity) {
+                        Ok(earned) => format!("You sell {} × {} for {} gold.", item.name, quantity, earned),
+                        Err(e) => trade_error_text(e, &item.name),
+                    }
+                });
+                match result {
+                    Ok((user, note)) => self.show_shop(&mut storage, &user, shop, Some(&note)),
+                    Err(e) => storage_failure("Failed to sell item", e),
+                }
+            }
+            // Travel links used to name the destination right after /adventure. Travelling takes an
+            // action token now, which old links do not have.
+            ["adventure", destination] if world.location(destination).is_some() =>
+                Response::redirect_permanent("/adventure".to_owned()),
+            ["account"] => {
+                let attributes = &user.attributes;
+                let stats = progression::stats(game, user.level, attributes);
                 Response::success(
                     MediaType::gemini(Some(Language::english())),
-                    format!("### Account\r\nName: {}\r\n### Actions\r\n=> /account/set-name 📝 Set name", user.name),
-                ),
+                    format!("### Account\r\nName: {}\r\n\
+                        ### Character\r\nLevel {} · HP: {} · Attack: {} · Defense: {}\r\n\
+                        {}: {} · {}: {} · {}: {}\r\n\
+                        ### Actions\r\n=> /account/set-name 📝 Set name\r\n=> /account/history 📜 History\r\n\
+                        => /account/certificates 🔑 Certificates\r\n=> /account/export 📦 Export your data\r\n\
+                        => /account/delete 🗑 Delete your account",
+                        user.name, user.level, user.max_health, stats.attack, stats.defense,
+                        Attribute::Strength.label(), attributes.strength,
+                        Attribute::Agility.label(), attributes.agility,
+                        Attribute::Vitality.label(), attributes.vitality),
+                )
+            }
+            ["account", "history"] => {
+                match storage.get_history(&user, HISTORY_LENGTH) {
+                    Ok(history) => history_page(&history),
+                    Err(e) => storage_failure("Failed to get history", e),
+                }
+            }
             ["account", "set-name"] => {
                 match request.query {
-                    None =>Response::input("Choose a name".to_owned()),
+                    None => Response::input("Choose a name".to_owned()),
                     Some(name) => {
                         match storage.update_name(user, name) {
                             Ok(_user) => Response::redirect_temporary("/account".to_owned()),
-                            Err(_) => Response::temporary_failure("Failed to update user".into())
+                            Err(e) => storage_failure("Failed to update user", e)
                         }
                     }
                 }
@@ -215,4 +1834,33 @@ impl Application {
             _ => Response::not_found("".to_owned())
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_split_action_token() {
+    assert_eq!(split_action_token(vec!["act", "12", "adventure", "rest"]), (Some(12), vec!["adventure", "rest"]));
+    assert_eq!(split_action_token(vec!["act", "twelve", "adventure", "rest"]), (None, vec!["adventure", "rest"]));
+    assert_eq!(split_action_token(vec!["adventure", "act", "12"]), (None, vec!["adventure", "act", "12"]));
+}
+
+#[test]
+fn test_has_secret_query() {
+    assert!(has_secret_query(&["account", "recovery", "set"]));
+    assert!(has_secret_query(&["account", "recover", "3"]));
+    assert!(has_secret_query(&["account", "certificates", "link"]));
+    assert!(!has_secret_query(&["account", "recovery"]));
+    assert!(!has_secret_query(&["account", "recovery", "remove"]));
+    assert!(!has_secret_query(&["account", "recoverX", "set"]));
+}
+
+#[test]
+fn test_changes_state() {
+    assert!(changes_state(&["adventure", "rest"], None));
+    assert!(changes_state(&["adventure", "fight", "attack"], None));
+    assert!(changes_state(&["adventure", "inventory", "drop", "herb"], None));
+    assert!(!changes_state(&["adventure", "inventory"], None));
+    assert!(!changes_state(&["adventure"], None));
+    // The question of how many to buy does not use up the token, the answer does.
+    assert!(!changes_state(&["adventure", "shop", "smithy", "buy", "sword"], None));
+    assert!(changes_state(&["adventure", "shop", "smithy", "buy", "sword"], Some("1")));
+}
@@ -0,0 +1,103 @@
+//! The core attributes of a character, bought with a budget of points when the character is
+//! created. Stats such as attack and maximum health are derived from them, see
+//! [crate::progression].
+
+use crate::config::GameConfig;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Attributes {
+    /// Adds to attack.
+    pub strength: i32,
+    /// Adds to defense, one point of defense for every two points of agility.
+    pub agility: i32,
+    /// Adds to maximum health.
+    pub vitality: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attribute {
+    Strength,
+    Agility,
+    Vitality,
+}
+
+impl Attribute {
+    pub const ALL: [Attribute; 3] = [Attribute::Strength, Attribute::Agility, Attribute::Vitality];
+
+    pub fn from_segment(segment: &str) -> Option<Attribute> {
+        Attribute::ALL.iter().copied().find(|attribute| attribute.segment() == segment)
+    }
+
+    pub fn segment(self) -> &'static str {
+        match self {
+            Attribute::Strength => "strength",
+            Attribute::Agility => "agility",
+            Attribute::Vitality => "vitality",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Attribute::Strength => "💪 Strength",
+            Attribute::Agility => "🤸 Agility",
+            Attribute::Vitality => "❤ Vitality",
+        }
+    }
+}
+
+impl Attributes {
+    pub fn get(&self, attribute: Attribute) -> i32 {
+        match attribute {
+            Attribute::Strength => self.strength,
+            Attribute::Agility => self.agility,
+            Attribute::Vitality => self.vitality,
+        }
+    }
+
+    /// Points spent on all attributes together.
+    pub fn total(&self) -> i32 {
+        self.strength + self.agility + self.vitality
+    }
+
+    /// Points left to spend at character creation.
+    pub fn points_left(&self, config: &GameConfig) -> i32 {
+        config.attribute_points - self.total()
+    }
+
+    /// Moves `change` points into, or out of, `attribute`. Returns `None` when that would go
+    /// below zero, above the maximum for an attribute or over the budget.
+    pub fn spend(&self, config: &GameConfig, attribute: Attribute, change: i32) -> Option<Attributes> {
+        let value = self.get(attribute) + change;
+        if !(0..=config.attribute_max).contains(&value) || change > self.points_left(config) {
+            return None;
+        }
+        let mut attributes = *self;
+        match attribute {
+            Attribute::Strength => attributes.strength = value,
+            Attribute::Agility => attributes.agility = value,
+            Attribute::Vitality => attributes.vitality = value,
+        }
+        Some(attributes)
+    }
+}
+
+#[test]
+fn test_spend() {
+    let config = GameConfig { attribute_points: 5, attribute_max: 3, ..GameConfig::default() };
+    let attributes = Attributes::default();
+    assert_eq!(attributes.points_left(&config), 5);
+
+    let attributes = attributes.spend(&config, Attribute::Strength, 1).unwrap();
+    let attributes = attributes.spend(&config, Attribute::Strength, 1).unwrap();
+    let attributes = attributes.spend(&config, Attribute::Strength, 1).unwrap();
+    assert_eq!(attributes.spend(&config, Attribute::Strength, 1), None);
+    let attributes = attributes.spend(&config, Attribute::Vitality, 2).unwrap();
+    assert_eq!(attributes.points_left(&config), 0);
+    assert_eq!(attributes.spend(&config, Attribute::Agility, 1), None);
+    assert_eq!(attributes.spend(&config, Attribute::Agility, -1), None);
+
+    let attributes = attributes.spend(&config, Attribute::Strength, -1).unwrap();
+    assert_eq!(attributes, Attributes { strength: 2, agility: 0, vitality: 2 });
+    assert_eq!(Attribute::from_segment("vitality"), Some(Attribute::Vitality));
+    assert_eq!(Attribute::from_segment("charm"), None);
+}
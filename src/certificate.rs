@@ -0,0 +1,129 @@
+//! Generates self-signed server certificates, so that a new deployment does not need to craft
+//! them by hand. Gemini clients trust on first use, so self-signed certificates are the norm.
+
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::x509::{X509, X509NameBuilder};
+use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyType {
+    Rsa2048,
+    Rsa4096,
+    EcdsaP256,
+    EcdsaP384,
+    Ed25519,
+}
+
+impl KeyType {
+    pub const NAMES: &'static [&'static str] = &["rsa2048", "rsa4096", "ecdsa-p256", "ecdsa-p384", "ed25519"];
+
+    fn generate(self) -> Result<PKey<Private>, ErrorStack> {
+        match self {
+            KeyType::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?),
+            KeyType::Rsa4096 => PKey::from_rsa(Rsa::generate(4096)?),
+            KeyType::EcdsaP256 => PKey::from_ec_key(EcKey::generate(EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?.as_ref())?),
+            KeyType::EcdsaP384 => PKey::from_ec_key(EcKey::generate(EcGroup::from_curve_name(Nid::SECP384R1)?.as_ref())?),
+            KeyType::Ed25519 => PKey::generate_ed25519(),
+        }
+    }
+
+    /// Ed25519 signs the message itself, every other key type signs a SHA-256 digest of it.
+    fn digest(self) -> MessageDigest {
+        match self {
+            KeyType::Ed25519 => MessageDigest::null(),
+            _ => MessageDigest::sha256(),
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rsa2048" => Ok(KeyType::Rsa2048),
+            "rsa4096" => Ok(KeyType::Rsa4096),
+            "ecdsa-p256" => Ok(KeyType::EcdsaP256),
+            "ecdsa-p384" => Ok(KeyType::EcdsaP384),
+            "ed25519" => Ok(KeyType::Ed25519),
+            _ => Err(format!("unknown key type {}, expected one of {}", s, KeyType::NAMES.join(", "))),
+        }
+    }
+}
+
+/// A certificate and its private key, both encoded in PEM format as expected by the server.
+pub struct GeneratedCertificate {
+    pub certificate_pem: Vec<u8>,
+    /// The private key in PKCS 8 format.
+    pub private_key_pem: Vec<u8>,
+}
+
+/// Generates a self-signed certificate for `hostname`, valid from now for `days` days.
+/// The hostname may also be an IP address.
+pub fn generate(hostname: &str, days: u32, key_type: KeyType) -> Result<GeneratedCertificate, ErrorStack> {
+    let key = key_type.generate()?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, hostname)?;
+    let name = name.build();
+
+    let mut serial_number = BigNum::new()?;
+    serial_number.rand(159, MsbOption::MAYBE_ZERO, false)?;
+    let serial_number = serial_number.to_asn1_integer()?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(days)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial_number)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    let mut alternative_name = SubjectAlternativeName::new();
+    match hostname.parse::<IpAddr>() {
+        Ok(_) => alternative_name.ip(hostname),
+        Err(_) => alternative_name.dns(hostname),
+    };
+    let alternative_name = alternative_name.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(alternative_name)?;
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    builder.append_extension(KeyUsage::new().critical().digital_signature().key_encipherment().build()?)?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+    builder.sign(&key, key_type.digest())?;
+
+    Ok(GeneratedCertificate {
+        certificate_pem: builder.build().to_pem()?,
+        private_key_pem: key.private_key_to_pem_pkcs8()?,
+    })
+}
+
+#[test]
+fn test_generate() {
+    for name in KeyType::NAMES {
+        let key_type = name.parse::<KeyType>().unwrap();
+        let generated = generate("namushul.example", 30, key_type).unwrap();
+
+        let certificate = X509::from_pem(&generated.certificate_pem).unwrap();
+        let key = PKey::private_key_from_pem(&generated.private_key_pem).unwrap();
+        assert!(certificate.public_key().unwrap().public_eq(&key));
+        assert!(certificate.verify(&key).unwrap());
+        let common_name = certificate.subject_name().entries_by_nid(Nid::COMMONNAME).next().unwrap();
+        assert_eq!(common_name.data().as_slice(), b"namushul.example");
+        let alternative_names = certificate.subject_alt_names().unwrap();
+        assert_eq!(alternative_names.get(0).unwrap().dnsname(), Some("namushul.example"));
+    }
+    assert!("dsa".parse::<KeyType>().is_err());
+}
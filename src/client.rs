@@ -0,0 +1,222 @@
+//! A small gemini client, for poking at the capsule while debugging it and for testing it.
+
+use std::{error, fmt, fs, io};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::Duration;
+
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode, SslVersion};
+use openssl::x509::X509;
+use url::Url;
+
+use crate::response::Status;
+
+const DEFAULT_PORT: u16 = 1965;
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// A header is a two digit status, a space, at most 1024 bytes of meta and CRLF.
+const MAX_HEADER_LENGTH: usize = 2 + 1 + 1024 + 2;
+
+/// A client certificate and its private key, presented to the server to identify the user.
+pub struct Identity {
+    certificate: X509,
+    private_key: PKey<Private>,
+}
+
+impl Identity {
+    pub fn from_pem_files(certificate_path: &Path, private_key_path: &Path) -> Result<Identity, Error> {
+        let certificate = X509::from_pem(&fs::read(certificate_path)?)?;
+        let private_key = PKey::private_key_from_pem(&fs::read(private_key_path)?)?;
+        Ok(Identity { certificate, private_key })
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientResponse {
+    pub status: Status,
+    /// The prompt, media type, redirect URL or failure reason, depending on the status.
+    pub meta: String,
+    /// Empty unless the status is [Status::Success].
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Tls(String),
+    InvalidUrl(String),
+    InvalidHeader(String),
+    TooManyRedirects(Url),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self { Error::Io(e) }
+}
+
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self { Error::Tls(e.to_string()) }
+}
+
+impl<S: fmt::Debug> From<openssl::ssl::HandshakeError<S>> for Error {
+    fn from(e: openssl::ssl::HandshakeError<S>) -> Self { Error::Tls(e.to_string()) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "connection failed: {}", e),
+            Error::Tls(message) => write!(f, "TLS failed: {}", message),
+            Error::InvalidUrl(message) => write!(f, "invalid URL: {}", message),
+            Error::InvalidHeader(message) => write!(f, "invalid response header: {}", message),
+            Error::TooManyRedirects(url) => write!(f, "too many redirects, last one to {}", url),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Splits a response into its status, meta and body.
+pub fn parse_response(response: &[u8]) -> Result<ClientResponse, Error> {
+    let header_end = response.windows(2).position(|window| window == b"\r\n")
+        .filter(|position| position + 2 <= MAX_HEADER_LENGTH)
+        .ok_or_else(|| Error::InvalidHeader("expected a header of at most 1029 bytes ending in CRLF".to_owned()))?;
+    let header = std::str::from_utf8(&response[..header_end])
+        .map_err(|_| Error::InvalidHeader("header is not valid utf8".to_owned()))?;
+    let (code, meta) = match header.split_once(' ') {
+        Some((code, meta)) => (code, meta),
+        None => (header, ""),
+    };
+    let status = code.parse::<u8>().ok()
+        .filter(|_| code.len() == 2)
+        .and_then(Status::from_code)
+        .ok_or_else(|| Error::InvalidHeader(format!("unknown status {}", code)))?;
+    let body = response[header_end + 2..].to_vec();
+    if !status.has_body() && !body.is_empty() {
+        return Err(Error::InvalidHeader(format!("status {} must not have a body", status.code())));
+    }
+    Ok(ClientResponse { status, meta: meta.to_owned(), body })
+}
+
+pub struct Client {
+    connector: SslConnector,
+}
+
+impl Client {
+    /// Gemini servers use self-signed certificates, so the server certificate is not verified.
+    pub fn new(identity: Option<&Identity>) -> Result<Client, Error> {
+        let mut connector = SslConnector::builder(SslMethod::tls_client())?;
+        connector.set_min_proto_version(Some(SslVersion::TLS1_2))?;
+        connector.set_verify(SslVerifyMode::NONE);
+        if let Some(identity) = identity {
+            connector.set_certificate(&identity.certificate)?;
+            connector.set_private_key(&identity.private_key)?;
+            connector.check_private_key()?;
+        }
+        Ok(Client { connector: connector.build() })
+    }
+
+    /// Sends a single request, without following redirects.
+    pub fn request(&self, url: &Url) -> Result<ClientResponse, Error> {
+        if url.scheme() != "gemini" {
+            return Err(Error::InvalidUrl(format!("{} is not a gemini URL", url)));
+        }
+        let host = url.host_str().ok_or_else(|| Error::InvalidUrl(format!("{} has no host", url)))?;
+        let stream = TcpStream::connect((host, url.port().unwrap_or(DEFAULT_PORT)))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut stream = self.connector.configure()?
+            .verify_hostname(false)
+            .connect(host, stream)?;
+        stream.write_all(format!("{}\r\n", url).as_bytes())?;
+
+        let mut response = vec![];
+        match stream.read_to_end(&mut response) {
+            Ok(_) => {}
+            // Not every server closes the TLS session cleanly.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionAborted && !response.is_empty() => {}
+            Err(e) => return Err(e.into()),
+        }
+        parse_response(&response)
+    }
+
+    /// Sends a request, following at most `max_redirects` redirects. Returns the URL that
+    /// answered last along with its response.
+    pub fn fetch(&self, url: Url, max_redirects: usize) -> Result<(Url, ClientResponse), Error> {
+        let mut url = url;
+        for _ in 0..=max_redirects {
+            let response = self.request(&url)?;
+            if !response.status.is_redirect() {
+                return Ok((url, response));
+            }
+            url = url.join(&response.meta).map_err(|e| Error::InvalidUrl(format!("{}: {}", response.meta, e)))?;
+        }
+        Err(Error::TooManyRedirects(url))
+    }
+}
+
+#[test]
+fn test_parse_response() {
+    let response = parse_response(b"20 text/gemini; lang=en\r\n# Hello\r\n").unwrap();
+    assert_eq!(response.status, Status::Success);
+    assert_eq!(response.meta, "text/gemini; lang=en");
+    assert_eq!(response.body, b"# Hello\r\n");
+
+    let response = parse_response(b"51 \r\n").unwrap();
+    assert_eq!(response.status, Status::NotFound);
+    assert_eq!(response.meta, "");
+
+    assert_eq!(parse_response(b"57 Weird\r\n").unwrap().status, Status::PermanentFailure);
+    assert!(parse_response(b"20 text/gemini").is_err());
+    assert!(parse_response(b"2 text/gemini\r\n").is_err());
+    assert!(parse_response(b"99 \r\n").is_err());
+    assert!(parse_response(b"30 /elsewhere\r\nbody").is_err());
+    assert!(parse_response(format!("20 {}\r\n", "x".repeat(1025)).as_bytes()).is_err());
+}
+
+#[test]
+fn test_fetch() {
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let acceptor = crate::gemini::make_acceptor(
+        Path::new("development_private_key.pem"),
+        Path::new("development_certificate.pem"),
+    );
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = acceptor.accept(stream.unwrap()).unwrap();
+            let url = crate::gemini::read_request(&mut stream, 1024).ok().unwrap();
+            let has_certificate = stream.ssl().peer_certificate().is_some();
+            let response = match url.path() {
+                "/start" => "30 /middle\r\n".to_owned(),
+                "/middle" => "31 end\r\n".to_owned(),
+                "/end" => format!("20 text/plain\r\ncertificate: {}", has_certificate),
+                "/loop" => "30 /loop\r\n".to_owned(),
+                _ => "51 Not found\r\n".to_owned(),
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+            stream.shutdown().unwrap();
+        }
+    });
+    let url = |path: &str| Url::parse(&format!("gemini://localhost:{}{}", port, path)).unwrap();
+
+    let client = Client::new(None).unwrap();
+    let (last_url, response) = client.fetch(url("/start"), 5).unwrap();
+    assert_eq!(last_url, url("/end"));
+    assert_eq!(response.status, Status::Success);
+    assert_eq!(response.body, b"certificate: false");
+
+    assert_eq!(client.fetch(url("/start"), 1).unwrap_err().to_string(), Error::TooManyRedirects(url("/end")).to_string());
+    assert!(matches!(client.fetch(url("/loop"), 5), Err(Error::TooManyRedirects(_))));
+    assert_eq!(client.request(&url("/missing")).unwrap().status, Status::NotFound);
+
+    let identity = Identity::from_pem_files(
+        Path::new("development_certificate.pem"),
+        Path::new("development_private_key.pem"),
+    ).unwrap();
+    let client = Client::new(Some(&identity)).unwrap();
+    assert_eq!(client.fetch(url("/start"), 5).unwrap().1.body, b"certificate: true");
+}
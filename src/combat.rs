@@ -0,0 +1,280 @@
+//! Turn-based fights between a character and a monster. Everything here is pure, the dice are
+//! passed in, so that fights can be replayed and tested.
+
+use std::cmp::max;
+
+use rand::Rng;
+use serde::Deserialize;
+
+/// The chance of getting away when fleeing. When it fails the monster gets a free attack.
+const FLEE_CHANCE: f64 = 0.5;
+/// Damage varies by up to this much either way.
+const DAMAGE_VARIANCE: i32 = 1;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Monster {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub health: i32,
+    pub attack: i32,
+    pub defense: i32,
+    /// Experience for defeating the monster.
+    pub xp: i32,
+    /// The least and the most gold the monster drops.
+    #[serde(default)]
+    pub gold: (i32, i32),
+    #[serde(default)]
+    pub loot: Vec<Loot>,
+}
+
+/// Something a monster may leave behind when defeated.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Loot {
+    /// Id of the item.
+    pub item: String,
+    /// Between 0 and 1.
+    pub chance: f64,
+    #[serde(default = "one")]
+    pub quantity: i32,
+}
+
+fn one() -> i32 { 1 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub attack: i32,
+    pub defense: i32,
+}
+
+/// What the character does in a round.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Move {
+    Attack,
+    /// Skip attacking to take half the damage from the monster.
+    Defend,
+    Flee,
+}
+
+impl Move {
+    pub fn from_segment(segment: &str) -> Option<Move> {
+        match segment {
+            "attack" => Some(Move::Attack),
+            "defend" => Some(Move::Defend),
+            "flee" => Some(Move::Flee),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Ongoing,
+    /// Holds the item ids and quantities, and the gold, the monster left behind.
+    Victory { items: Vec<(String, i32)>, gold: i32 },
+    Defeat,
+    Fled,
+}
+
+/// The result of a round, with a line of text for everything that happened.
+#[derive(Debug)]
+pub struct Round {
+    pub log: Vec<String>,
+    pub outcome: Outcome,
+    pub health: i32,
+    pub monster_health: i32,
+}
+
+/// Damage dealt by an attack, which always hurts at least a little.
+pub fn damage(attack: i32, defense: i32, roll: i32) -> i32 {
+    max(1, attack + roll - defense)
+}
+
+fn roll_damage(attack: i32, defense: i32, rng: &mut impl Rng) -> i32 {
+    damage(attack, defense, rng.gen_range(-DAMAGE_VARIANCE..=DAMAGE_VARIANCE))
+}
+
+impl Monster {
+    pub fn roll_loot(&self, rng: &mut impl Rng) -> Vec<(String, i32)> {
+        self.loot.iter()
+            .filter(|loot| rng.gen_bool(loot.chance))
+            .map(|loot| (loot.item.clone(), loot.quantity))
+            .collect()
+    }
+
+    pub fn roll_gold(&self, rng: &mut impl Rng) -> i32 {
+        let (least, most) = self.gold;
+        rng.gen_range(least..=most)
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.gold.0 < 0 || self.gold.0 > self.gold.1 {
+            problems.push(format!("monster {} must drop a non-negative range of gold", self.id));
+        }
+        if self.health <= 0 {
+            problems.push(format!("monster {} must have positive health", self.id));
+        }
+        if self.attack < 0 || self.defense < 0 || self.xp < 0 {
+            problems.push(format!("monster {} must not have negative attack, defense or xp", self.id));
+        }
+        for loot in &self.loot {
+            if !(0.0..=1.0).contains(&loot.chance) {
+                problems.push(format!("monster {} drops {} with a chance outside of 0 to 1", self.id, loot.item));
+            }
+            if loot.quantity <= 0 {
+                problems.push(format!("monster {} drops a non-positive quantity of {}", self.id, loot.item));
+            }
+        }
+        problems
+    }
+}
+
+/// Plays one round: the character moves first, then the monster strikes back if it still can.
+pub fn play_round(
+    stats: Stats,
+    health: i32,
+    monster: &Monster,
+    monster_health: i32,
+    character_move: Move,
+    rng: &mut impl Rng,
+) -> Round {
+    let mut log = vec![];
+    let mut health = health;
+    let mut monster_health = monster_health;
+    let mut defending = false;
+
+    match character_move {
+        Move::Attack => {
+            let dealt = roll_damage(stats.attack, monster.defense, rng);
+            monster_health = max(0, monster_health - dealt);
+            log.push(format!("You hit the {} for {} damage.", monster.name, dealt));
+            if monster_health == 0 {
+                log.push(format!("The {} is defeated!", monster.name));
+                let outcome = Outcome::Victory { items: monster.roll_loot(rng), gold: monster.roll_gold(rng) };
+                return Round { log, outcome, health, monster_health };
+            }
+        }
+        Move::Defend => {
+            defending = true;
+            log.push("You raise your guard.".to_owned());
+        }
+        Move::Flee => {
+            if rng.gen_bool(FLEE_CHANCE) {
+                log.push(format!("You get away from the {}.", monster.name));
+                return Round { log, outcome: Outcome::Fled, health, monster_health };
+            }
+            log.push("You try to run, but cannot get away.".to_owned());
+        }
+    }
+
+    let mut taken = roll_damage(monster.attack, stats.defense, rng);
+    if defending {
+        taken = (taken + 1) / 2;
+    }
+    health = max(0, health - taken);
+    log.push(format!("The {} hits you for {} damage.", monster.name, taken));
+    if health == 0 {
+        log.push(format!("You have been defeated by the {}.", monster.name));
+        return Round { log, outcome: Outcome::Defeat, health, monster_health };
+    }
+    Round { log, outcome: Outcome::Ongoing, health, monster_health }
+}
+
+#[cfg(test)]
+fn test_monster() -> Monster {
+    Monster {
+        id: "slime".to_owned(),
+        name: "slime".to_owned(),
+        description: "A blob.".to_owned(),
+        health: 4,
+        attack: 2,
+        defense: 1,
+        xp: 3,
+        gold: (2, 2),
+        loot: vec![
+            Loot { item: "jelly".to_owned(), chance: 1.0, quantity: 2 },
+            Loot { item: "gem".to_owned(), chance: 0.0, quantity: 1 },
+        ],
+    }
+}
+
+#[test]
+fn test_damage() {
+    assert_eq!(damage(3, 1, 0), 2);
+    assert_eq!(damage(3, 1, 1), 3);
+    assert_eq!(damage(3, 1, -1), 1);
+    assert_eq!(damage(1, 5, 0), 1);
+}
+
+#[test]
+fn test_play_round() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let monster = test_monster();
+    let stats = Stats { attack: 3, defense: 0 };
+    for seed in 0..100 {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        // Attacks deal between 1 and 3 damage, so a monster at 1 HP always falls.
+        let round = play_round(stats, 10, &monster, 1, Move::Attack, &mut rng);
+        assert_eq!(round.outcome, Outcome::Victory { items: vec![("jelly".to_owned(), 2)], gold: 2 });
+        assert_eq!(round.health, 10);
+
+        let round = play_round(stats, 10, &monster, 4, Move::Attack, &mut rng);
+        assert_eq!(round.outcome, Outcome::Ongoing);
+        assert!((1..=3).contains(&round.monster_health));
+        assert!((7..=9).contains(&round.health));
+
+        // Defending halves the at most 2 damage the monster deals.
+        let round = play_round(Stats { attack: 3, defense: 1 }, 10, &monster, 4, Move::Defend, &mut rng);
+        assert_eq!(round.monster_health, 4);
+        assert_eq!(round.health, 9);
+
+        let round = play_round(stats, 1, &monster, 4, Move::Defend, &mut rng);
+        assert_eq!(round.outcome, Outcome::Defeat);
+        assert_eq!(round.health, 0);
+
+        let round = play_round(stats, 10, &monster, 4, Move::Flee, &mut rng);
+        match round.outcome {
+            Outcome::Fled => assert_eq!(round.health, 10),
+            Outcome::Ongoing => assert!(round.health < 10),
+            outcome => panic!("unexpected {:?}", outcome),
+        }
+    }
+
+    // The same dice give the same fight.
+    let fight = |seed| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let round = play_round(stats, 10, &monster, 4, Move::Flee, &mut rng);
+        (round.log, round.health)
+    };
+    assert_eq!(fight(7), fight(7));
+}
+
+#[test]
+fn test_flee() {
+    use crate::random::Random;
+
+    // A roll of 0 is as lucky as it gets, and one of the largest number as unlucky.
+    let monster = test_monster();
+    let stats = Stats { attack: 3, defense: 0 };
+    let mut dice = Random::Scripted(vec![0]).dice(1, 1);
+    let round = play_round(stats, 10, &monster, 4, Move::Flee, &mut dice);
+    assert_eq!((round.outcome, round.health), (Outcome::Fled, 10));
+
+    let mut dice = Random::Scripted(vec![u64::MAX, 0]).dice(1, 1);
+    let round = play_round(stats, 10, &monster, 4, Move::Flee, &mut dice);
+    assert_eq!((round.outcome, round.health), (Outcome::Ongoing, 9));
+}
+
+#[test]
+fn test_validate_monster() {
+    assert!(test_monster().validate().is_empty());
+    let monster = Monster { health: 0, loot: vec![Loot { item: "x".to_owned(), chance: 2.0, quantity: 0 }], ..test_monster() };
+    assert_eq!(monster.validate().len(), 3);
+    assert_eq!(Monster { gold: (3, 1), ..test_monster() }.validate().len(), 1);
+}
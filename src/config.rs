@@ -0,0 +1,438 @@
+//! Settings for the server.
+//!
+//! Values are layered: the built in defaults are overridden by the TOML configuration file,
+//! which is overridden by environment variables, which are overridden by command line flags.
+//! Any value can be set from the environment as `NAMUSHUL_<SECTION>__<KEY>`, for example
+//! `NAMUSHUL_GAME__STARTING_HEALTH=12`, and from the command line as
+//! `--set game.starting_health=12`.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{error, fmt, fs, io};
+
+use serde::{Deserialize, Serialize};
+use toml::Value;
+use toml::value::Table;
+
+use crate::attributes::Attribute;
+
+/// The file read when no configuration file is given explicitly. It is fine for it to not exist.
+pub const DEFAULT_PATH: &str = "namushul.toml";
+
+const ENV_PREFIX: &str = "NAMUSHUL_";
+const ENV_SEPARATOR: &str = "__";
+
+/// Environment variables that were in use before the configuration file existed.
+const LEGACY_ENV: [(&str, &str); 4] = [
+    ("POSTGRES_HOST", "storage.host"),
+    ("POSTGRES_USER", "storage.user"),
+    ("POSTGRES_PASSWORD", "storage.password"),
+    ("SIMULATE_LATENCY", "transport.simulate_latency"),
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub transport: TransportConfig,
+    pub storage: StorageConfig,
+    pub game: GameConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportConfig {
+    /// Bind server to this address
+    pub address: SocketAddr,
+    /// Path to a certificate file encoded in PEM format for TLS connections
+    pub certificates_path: Option<PathBuf>,
+    /// Path to a PKCS 8 or RSA keyfile encoded in PEM format for TLS connections
+    pub private_key_path: Option<PathBuf>,
+    /// Longest URL accepted in a request. The gemini specification allows up to 1024 bytes.
+    pub max_url_length: usize,
+    /// Delay every request by a second, to see how the capsule feels on a slow connection
+    pub simulate_latency: bool,
+}
+
+impl TransportConfig {
+    fn tls_paths_by_key(&self) -> [(&'static str, Option<&Path>); 2] {
+        [
+            ("transport.certificates_path", self.certificates_path.as_deref()),
+            ("transport.private_key_path", self.private_key_path.as_deref()),
+        ]
+    }
+
+    /// The certificate and private key paths, which are only needed when serving. They need not
+    /// exist before that, so that `namushul gen-cert` can create them.
+    pub fn tls_paths(&self) -> Result<(&Path, &Path), Error> {
+        let problems = self.tls_paths_by_key().iter()
+            .filter_map(|(key, path)| match path {
+                None => Some(format!("{} is not set", key)),
+                Some(path) if !path.is_file() => Some(format!("{} {} is not a file", key, path.display())),
+                Some(_) => None,
+            })
+            .collect::<Vec<_>>();
+        match (self.certificates_path.as_deref(), self.private_key_path.as_deref()) {
+            (Some(certificates_path), Some(private_key_path)) if problems.is_empty() => Ok((certificates_path, private_key_path)),
+            _ => Err(Error::Invalid(problems)),
+        }
+    }
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig {
+            address: ([0, 0, 0, 0], 1965).into(),
+            certificates_path: None,
+            private_key_path: None,
+            max_url_length: 1024,
+            simulate_latency: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub host: String,
+    pub user: String,
+    pub password: String,
+    /// Apply pending migrations when the server starts, instead of requiring `namushul migrate`
+    pub migrate_on_startup: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            host: "localhost".to_owned(),
+            user: "postgres".to_owned(),
+            password: "postgres".to_owned(),
+            migrate_on_startup: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    /// Directory with the content files, such as `world.toml`
+    pub content_path: PathBuf,
+    /// The most characters an account may have
+    pub max_characters: i32,
+    /// Minutes a pairing code, for linking another certificate to an account, can be used for
+    pub pairing_code_minutes: u64,
+    /// Attempts at entering the recovery secret of an account allowed within the recovery window
+    pub recovery_attempts: i32,
+    /// Minutes in which the attempts at recovering an account are counted
+    pub recovery_window_minutes: u64,
+    /// Health, and maximum health, of a newly created character without any vitality
+    pub starting_health: i32,
+    /// Gold of a newly created character
+    pub starting_gold: i32,
+    /// What a night at an inn costs, in gold
+    pub rest_price: i32,
+    /// Seconds it takes to regenerate a point of health, or 0 for no regeneration
+    pub health_regen_seconds: u64,
+    /// Seed every roll of the dice is derived from, or 0 to pick one when the server starts. The
+    /// seed in use is logged, so that what happened to a character can be replayed
+    pub random_seed: u64,
+    /// Points to spend on attributes when creating a character, all of which must be spent
+    pub attribute_points: i32,
+    /// The most points a new character may put into a single attribute
+    pub attribute_max: i32,
+    /// Maximum health gained with each point of vitality
+    pub vitality_health: i32,
+    /// Inventory slots of every character. A slot holds a stack of the same item
+    pub inventory_slots: i32,
+    /// Share of their maximum health, in percent, that knocked out characters wake up with
+    pub knockout_health_percent: i32,
+    /// Share of the experience gained since their last level up, in percent, that knocked out
+    /// characters lose
+    pub knockout_xp_percent: i32,
+    /// Share of their gold, in percent, that knocked out characters lose
+    pub knockout_gold_percent: i32,
+    /// Attack of every character in a fight, before anything else adds to it
+    pub base_attack: i32,
+    /// Defense of every character in a fight, before anything else adds to it
+    pub base_defense: i32,
+    /// Experience needed to reach level 2, level 3 and so on. Its length decides the highest level
+    pub level_xp: Vec<i32>,
+    /// Maximum health gained with each level
+    pub level_health: i32,
+    /// Attack gained with each level
+    pub level_attack: i32,
+    /// Defense gained with each level
+    pub level_defense: i32,
+}
+
+impl GameConfig {
+    /// How long it takes to regenerate a point of health, or `None` when health does not
+    /// regenerate.
+    pub fn health_regeneration(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.health_regen_seconds)).filter(|interval| !interval.is_zero())
+    }
+
+    /// The configured seed, or `None` when one should be picked.
+    pub fn random_seed(&self) -> Option<u64> {
+        Some(self.random_seed).filter(|&seed| seed != 0)
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            content_path: "content".into(),
+            max_characters: 5,
+            pairing_code_minutes: 10,
+            recovery_attempts: 5,
+            recovery_window_minutes: 60,
+            starting_health: 10,
+            starting_gold: 10,
+            rest_price: 5,
+            health_regen_seconds: 60,
+            random_seed: 0,
+            attribute_points: 6,
+            attribute_max: 4,
+            vitality_health: 2,
+            inventory_slots: 12,
+            knockout_health_percent: 50,
+            knockout_xp_percent: 25,
+            knockout_gold_percent: 10,
+            base_attack: 3,
+            base_defense: 0,
+            level_xp: vec![10, 25, 50, 100, 200, 350, 550, 800, 1100],
+            level_health: 2,
+            level_attack: 1,
+            level_defense: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration file could not be read.
+    Io(PathBuf, io::Error),
+    /// The configuration file is not valid TOML, or does not match the expected structure.
+    Parse(String),
+    /// An override from the environment or command line could not be applied.
+    Override(String, String),
+    /// The configuration was read, but some values are not acceptable. Holds every problem found.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            Error::Parse(message) => write!(f, "failed to parse configuration: {}", message),
+            Error::Override(key, message) => write!(f, "failed to override {}: {}", key, message),
+            Error::Invalid(problems) => write!(f, "invalid configuration:\n  {}", problems.join("\n  ")),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl Config {
+    /// Reads the configuration from `path`, or from [DEFAULT_PATH] if it exists, then applies
+    /// overrides from the process environment and `overrides` given as `key=value` pairs.
+    pub fn load(path: Option<&Path>, overrides: &[(String, String)]) -> Result<Config, Error> {
+        let contents = match path {
+            Some(path) => Some(fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?),
+            None => match fs::read_to_string(DEFAULT_PATH) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(Error::Io(DEFAULT_PATH.into(), e)),
+            }
+        };
+        Config::from_layers(contents.as_deref(), std::env::vars(), overrides)
+    }
+
+    /// Builds a configuration from the contents of a configuration file, environment variables
+    /// and command line overrides, then validates it.
+    pub fn from_layers(
+        file: Option<&str>,
+        env: impl IntoIterator<Item=(String, String)>,
+        overrides: &[(String, String)],
+    ) -> Result<Config, Error> {
+        let defaults = Value::try_from(Config::default()).map_err(|e| Error::Parse(e.to_string()))?;
+        let mut layered = match file {
+            Some(contents) => contents.parse::<Value>().map_err(|e| Error::Parse(e.to_string()))?,
+            None => Value::Table(Table::new()),
+        };
+
+        let env = env.into_iter().collect::<Vec<_>>();
+        let legacy = LEGACY_ENV.iter().filter_map(|(name, key)| {
+            env.iter().find(|(n, _)| n == name).map(|(_, value)| (key.to_string(), value.clone()))
+        });
+        let prefixed = env.iter().filter_map(|(name, value)| {
+            let path = name.strip_prefix(ENV_PREFIX)?;
+            if !path.contains(ENV_SEPARATOR) { return None; }
+            Some((path.to_lowercase().replace(ENV_SEPARATOR, "."), value.clone()))
+        });
+        for (key, raw) in legacy.chain(prefixed).collect::<Vec<_>>().iter().chain(overrides) {
+            let value = parse_override(&defaults, key, raw)?;
+            set(&mut layered, key, value)?;
+        }
+
+        let config: Config = layered.try_into().map_err(|e: toml::de::Error| Error::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values that deserialize fine but make no sense.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = vec![];
+        let transport = &self.transport;
+        if !(1..=1024).contains(&transport.max_url_length) {
+            problems.push(format!("transport.max_url_length must be between 1 and 1024, got {}", transport.max_url_length));
+        }
+        if self.storage.host.is_empty() {
+            problems.push("storage.host must not be empty".to_owned());
+        }
+        if self.game.max_characters < 1 {
+            problems.push(format!("game.max_characters must be at least 1, got {}", self.game.max_characters));
+        }
+        if self.game.pairing_code_minutes < 1 {
+            problems.push("game.pairing_code_minutes must be at least 1".to_owned());
+        }
+        if self.game.recovery_attempts < 1 || self.game.recovery_window_minutes < 1 {
+            problems.push("game.recovery_attempts and game.recovery_window_minutes must be at least 1".to_owned());
+        }
+        if self.game.starting_health <= 0 {
+            problems.push(format!("game.starting_health must be positive, got {}", self.game.starting_health));
+        }
+        if self.game.starting_gold < 0 || self.game.rest_price < 0 {
+            problems.push("game.starting_gold and game.rest_price must not be negative".to_owned());
+        }
+        if self.game.attribute_points < 0 || self.game.attribute_max < 0 || self.game.vitality_health < 0 {
+            problems.push("game.attribute_points, game.attribute_max and game.vitality_health must not be negative".to_owned());
+        }
+        // Characters are only created once every point is spent, which must be possible.
+        let most = self.game.attribute_max.saturating_mul(Attribute::ALL.len() as i32);
+        if self.game.attribute_points > most {
+            problems.push(format!("game.attribute_points must be at most {}, all attributes at game.attribute_max, got {}",
+                most, self.game.attribute_points));
+        }
+        if self.game.inventory_slots < 1 {
+            problems.push(format!("game.inventory_slots must be at least 1, got {}", self.game.inventory_slots));
+        }
+        if !(1..=100).contains(&self.game.knockout_health_percent) {
+            problems.push(format!("game.knockout_health_percent must be between 1 and 100, got {}", self.game.knockout_health_percent));
+        }
+        if !(0..=100).contains(&self.game.knockout_xp_percent) {
+            problems.push(format!("game.knockout_xp_percent must be between 0 and 100, got {}", self.game.knockout_xp_percent));
+        }
+        if !(0..=100).contains(&self.game.knockout_gold_percent) {
+            problems.push(format!("game.knockout_gold_percent must be between 0 and 100, got {}", self.game.knockout_gold_percent));
+        }
+        if self.game.base_attack < 0 || self.game.base_defense < 0 {
+            problems.push("game.base_attack and game.base_defense must not be negative".to_owned());
+        }
+        let curve = &self.game.level_xp;
+        if curve.first().is_some_and(|&xp| xp <= 0) || curve.windows(2).any(|pair| pair[0] >= pair[1]) {
+            problems.push("game.level_xp must only hold positive, increasing amounts of experience".to_owned());
+        }
+        if self.game.level_health < 0 || self.game.level_attack < 0 || self.game.level_defense < 0 {
+            problems.push("game.level_health, game.level_attack and game.level_defense must not be negative".to_owned());
+        }
+        if problems.is_empty() { Ok(()) } else { Err(Error::Invalid(problems)) }
+    }
+}
+
+/// Splits a `key=value` command line argument.
+pub fn parse_key_value(argument: &str) -> Result<(String, String), String> {
+    match argument.split_once('=') {
+        Some((key, value)) => Ok((key.trim().to_owned(), value.to_owned())),
+        None => Err(format!("expected key=value, got {}", argument)),
+    }
+}
+
+/// Interprets a raw override as the type of the default value at `key`, so that a password of
+/// `1234` stays a string while a health of `12` becomes an integer.
+fn parse_override(defaults: &Value, key: &str, raw: &str) -> Result<Value, Error> {
+    let default = key.split('.').try_fold(defaults, |value, segment| value.get(segment));
+    match default {
+        Some(Value::String(_)) | None => Ok(Value::String(raw.to_owned())),
+        Some(_) => match format!("value = {}", raw).parse::<Value>() {
+            Ok(Value::Table(mut table)) => Ok(table.remove("value").unwrap()),
+            Ok(_) => unreachable!("a TOML document is always a table"),
+            Err(e) => Err(Error::Override(key.to_owned(), e.to_string())),
+        }
+    }
+}
+
+/// Sets the value at a dotted `key` such as `game.starting_health`, creating tables on the way.
+fn set(root: &mut Value, key: &str, value: Value) -> Result<(), Error> {
+    let mut segments = key.split('.').collect::<Vec<_>>();
+    let last = segments.pop().filter(|s| !s.is_empty())
+        .ok_or_else(|| Error::Override(key.to_owned(), "empty key".to_owned()))?;
+    let mut table = root;
+    for segment in segments {
+        table = match table {
+            Value::Table(t) => t.entry(segment).or_insert_with(|| Value::Table(Table::new())),
+            _ => return Err(Error::Override(key.to_owned(), format!("{} is not a table", segment))),
+        };
+    }
+    match table {
+        Value::Table(t) => {
+            t.insert(last.to_owned(), value);
+            Ok(())
+        }
+        _ => Err(Error::Override(key.to_owned(), "parent is not a table".to_owned())),
+    }
+}
+
+#[cfg(test)]
+fn with_certificates(overrides: &[(&str, &str)]) -> Vec<(String, String)> {
+    let mut all = vec![
+        ("transport.certificates_path".to_owned(), "development_certificate.pem".to_owned()),
+        ("transport.private_key_path".to_owned(), "development_private_key.pem".to_owned()),
+    ];
+    all.extend(overrides.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+    all
+}
+
+#[test]
+fn test_layering() {
+    let file = "[game]\nstarting_health = 12\n[storage]\nhost = \"db\"\nuser = \"file\"";
+    let env = vec![
+        ("POSTGRES_USER".to_owned(), "legacy".to_owned()),
+        ("NAMUSHUL_STORAGE__PASSWORD".to_owned(), "1234".to_owned()),
+        ("NAMUSHUL_GAME__STARTING_HEALTH".to_owned(), "15".to_owned()),
+    ];
+    let config = Config::from_layers(Some(file), env, &with_certificates(&[("game.starting_health", "20")])).unwrap();
+    assert_eq!(config.storage.host, "db");
+    assert_eq!(config.storage.user, "legacy");
+    assert_eq!(config.storage.password, "1234");
+    assert_eq!(config.game.starting_health, 20);
+    assert_eq!(config.transport.max_url_length, 1024);
+}
+
+#[test]
+fn test_validation() {
+    let config = Config::from_layers(None, vec![], &[]).unwrap();
+    assert!(matches!(config.transport.tls_paths(), Err(Error::Invalid(problems)) if problems.len() == 2));
+    let config = Config::from_layers(None, vec![], &[("transport.private_key_path".to_owned(), "missing.pem".to_owned())]).unwrap();
+    assert!(matches!(config.transport.tls_paths(), Err(Error::Invalid(problems)) if problems.len() == 2));
+    let config = Config::from_layers(None, vec![], &with_certificates(&[])).unwrap();
+    assert!(config.transport.tls_paths().is_ok());
+    assert!(matches!(
+        Config::from_layers(None, vec![], &with_certificates(&[("game.starting_health", "0")])),
+        Err(Error::Invalid(_))
+    ));
+    assert!(matches!(
+        Config::from_layers(None, vec![], &with_certificates(&[("game.level_xp", "[10, 5]")])),
+        Err(Error::Invalid(problems)) if problems[0].contains("level_xp")
+    ));
+    assert!(matches!(
+        Config::from_layers(None, vec![], &with_certificates(&[("game.attribute_points", "13"), ("game.attribute_max", "4")])),
+        Err(Error::Invalid(problems)) if problems[0].contains("attribute_points")
+    ));
+    assert!(Config::from_layers(None, vec![], &with_certificates(&[("game.attribute_points", "12"), ("game.attribute_max", "4")])).is_ok());
+    assert!(matches!(Config::from_layers(Some("[game]\nstarting_hp = 3"), vec![], &with_certificates(&[])), Err(Error::Parse(_))));
+    assert!(matches!(
+        Config::from_layers(None, vec![], &with_certificates(&[("transport.max_url_length", "many")])),
+        Err(Error::Override(_, _))
+    ));
+}
@@ -0,0 +1,259 @@
+//! Game content written by hand, such as the world map, read from TOML files in the content
+//! directory when the server starts.
+
+use std::{error, fmt, fs, io};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::combat::Monster;
+use crate::dialogue::Npc;
+use crate::items::{self, Item};
+use crate::quests::{Objective, Quest};
+use crate::shops::Shop;
+use crate::world::{Happening, World};
+
+const WORLD_FILE: &str = "world.toml";
+const MONSTERS_FILE: &str = "monsters.toml";
+const ITEMS_FILE: &str = "items.toml";
+const SHOPS_FILE: &str = "shops.toml";
+const QUESTS_FILE: &str = "quests.toml";
+const NPCS_FILE: &str = "npcs.toml";
+
+#[derive(Debug)]
+pub struct Content {
+    pub world: World,
+    pub monsters: Vec<Monster>,
+    pub items: Vec<Item>,
+    pub shops: Vec<Shop>,
+    pub quests: Vec<Quest>,
+    pub npcs: Vec<Npc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MonstersFile {
+    monsters: Vec<Monster>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ItemsFile {
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShopsFile {
+    shops: Vec<Shop>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuestsFile {
+    quests: Vec<Quest>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NpcsFile {
+    npcs: Vec<Npc>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    /// The files parsed fine, but do not make sense together. Holds every problem found.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            Error::Parse(path, message) => write!(f, "failed to parse {}: {}", path.display(), message),
+            Error::Invalid(problems) => write!(f, "invalid content:\n  {}", problems.join("\n  ")),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+fn read(directory: &Path, file: &str) -> Result<(PathBuf, String), Error> {
+    let path = directory.join(file);
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok((path, contents)),
+        Err(e) => Err(Error::Io(path, e)),
+    }
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(directory: &Path, file: &str) -> Result<T, Error> {
+    let (path, contents) = read(directory, file)?;
+    toml::from_str(&contents).map_err(|e| Error::Parse(path, e.to_string()))
+}
+
+impl Content {
+    /// Reads and validates all content files in `directory`.
+    pub fn load(directory: &Path) -> Result<Content, Error> {
+        let (path, contents) = read(directory, WORLD_FILE)?;
+        let world = World::from_toml(&contents).map_err(|e| Error::Parse(path, e.to_string()))?;
+        let MonstersFile { monsters } = parse(directory, MONSTERS_FILE)?;
+        let ItemsFile { items } = parse(directory, ITEMS_FILE)?;
+        let ShopsFile { shops } = parse(directory, SHOPS_FILE)?;
+        let QuestsFile { quests } = parse(directory, QUESTS_FILE)?;
+        let NpcsFile { npcs } = parse(directory, NPCS_FILE)?;
+
+        let content = Content { world, monsters, items, shops, quests, npcs };
+        let problems = content.validate();
+        if problems.is_empty() { Ok(content) } else { Err(Error::Invalid(problems)) }
+    }
+
+    pub fn monster(&self, id: &str) -> Option<&Monster> {
+        self.monsters.iter().find(|monster| monster.id == id)
+    }
+
+    pub fn item(&self, id: &str) -> Option<&Item> {
+        items::find(&self.items, id)
+    }
+
+    /// The name of an item, or its id when it no longer exists.
+    pub fn item_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.item(id).map_or(id, |item| &item.name)
+    }
+
+    pub fn shop(&self, id: &str) -> Option<&Shop> {
+        self.shops.iter().find(|shop| shop.id == id)
+    }
+
+    pub fn shops_at<'a>(&'a self, location: &'a str) -> impl Iterator<Item = &'a Shop> {
+        self.shops.iter().filter(move |shop| shop.location == location)
+    }
+
+    pub fn quest(&self, id: &str) -> Option<&Quest> {
+        self.quests.iter().find(|quest| quest.id == id)
+    }
+
+    pub fn quests_at<'a>(&'a self, location: &'a str) -> impl Iterator<Item = &'a Quest> {
+        self.quests.iter().filter(move |quest| quest.location == location)
+    }
+
+    pub fn npc(&self, id: &str) -> Option<&Npc> {
+        self.npcs.iter().find(|npc| npc.id == id)
+    }
+
+    pub fn npcs_at<'a>(&'a self, location: &'a str) -> impl Iterator<Item = &'a Npc> {
+        self.npcs.iter().filter(move |npc| npc.location.as_deref() == Some(location))
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = self.world.validate();
+        for location in self.world.locations() {
+            for monster in location.monsters.iter().filter(|id| self.monster(id).is_none()) {
+                problems.push(format!("location {} has monster {}, which does not exist", location.id, monster));
+            }
+            for encounter in &location.encounters {
+                let missing = match encounter.happening() {
+                    Happening::Monster(id) if self.monster(id).is_none() => Some(("monster", id)),
+                    Happening::Item(id, _) if self.item(id).is_none() => Some(("item", id)),
+                    Happening::Npc(id) if self.npc(id).is_none() => Some(("character", id)),
+                    _ => None,
+                };
+                if let Some((kind, id)) = missing {
+                    problems.push(format!("encounter at {} has {} {}, which does not exist", location.id, kind, id));
+                }
+            }
+            for exit in &location.exits {
+                if let Some(item) = exit.requires.item.as_ref().filter(|id| self.item(id).is_none()) {
+                    problems.push(format!("exit from {} to {} requires item {}, which does not exist", location.id, exit.to, item));
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        for monster in &self.monsters {
+            if !seen.insert(&monster.id) {
+                problems.push(format!("monster {} is defined more than once", monster.id));
+            }
+            problems.extend(monster.validate());
+            for loot in monster.loot.iter().filter(|loot| self.item(&loot.item).is_none()) {
+                problems.push(format!("monster {} drops item {}, which does not exist", monster.id, loot.item));
+            }
+        }
+        let mut seen = HashSet::new();
+        for item in &self.items {
+            if !seen.insert(&item.id) {
+                problems.push(format!("item {} is defined more than once", item.id));
+            }
+            problems.extend(item.validate());
+        }
+        let mut seen = HashSet::new();
+        for shop in &self.shops {
+            if !seen.insert(&shop.id) {
+                problems.push(format!("shop {} is defined more than once", shop.id));
+            }
+            problems.extend(shop.validate());
+            if self.world.location(&shop.location).is_none() {
+                problems.push(format!("shop {} is at location {}, which does not exist", shop.id, shop.location));
+            }
+            for stock in shop.stock.iter().filter(|stock| self.item(&stock.item).is_none()) {
+                problems.push(format!("shop {} sells item {}, which does not exist", shop.id, stock.item));
+            }
+        }
+        let mut seen = HashSet::new();
+        for quest in &self.quests {
+            if !seen.insert(&quest.id) {
+                problems.push(format!("quest {} is defined more than once", quest.id));
+            }
+            problems.extend(quest.validate());
+            if self.world.location(&quest.location).is_none() {
+                problems.push(format!("quest {} is given at location {}, which does not exist", quest.id, quest.location));
+            }
+            for required in quest.requires.quests.iter().filter(|id| self.quest(id).is_none()) {
+                problems.push(format!("quest {} requires quest {}, which does not exist", quest.id, required));
+            }
+            for objective in &quest.objectives {
+                let missing = match objective {
+                    Objective::Kill { monster, .. } if self.monster(monster).is_none() => Some(("monster", monster)),
+                    Objective::Reach { location } if self.world.location(location).is_none() => Some(("location", location)),
+                    Objective::Deliver { item, .. } if self.item(item).is_none() => Some(("item", item)),
+                    _ => None,
+                };
+                if let Some((kind, id)) = missing {
+                    problems.push(format!("quest {} asks for {} {}, which does not exist", quest.id, kind, id));
+                }
+            }
+            for reward in quest.rewards.items.iter().filter(|reward| self.item(&reward.item).is_none()) {
+                problems.push(format!("quest {} rewards item {}, which does not exist", quest.id, reward.item));
+            }
+        }
+        let mut seen = HashSet::new();
+        for npc in &self.npcs {
+            if !seen.insert(&npc.id) {
+                problems.push(format!("character {} is defined more than once", npc.id));
+            }
+            problems.extend(npc.validate());
+            if let Some(location) = npc.location.as_ref().filter(|id| self.world.location(id).is_none()) {
+                problems.push(format!("character {} is at location {}, which does not exist", npc.id, location));
+            }
+            for choice in npc.nodes.iter().flat_map(|node| &node.choices) {
+                let items = choice.conditions.item.iter().chain(choice.effects.give_item.iter().map(|gift| &gift.item));
+                for item in items.filter(|id| self.item(id).is_none()) {
+                    problems.push(format!("character {} mentions item {}, which does not exist", npc.id, item));
+                }
+                let quests = choice.conditions.quest.iter().chain(choice.effects.start_quest.iter());
+                for quest in quests.filter(|id| self.quest(id).is_none()) {
+                    problems.push(format!("character {} mentions quest {}, which does not exist", npc.id, quest));
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[test]
+fn test_shipped_content_is_valid() {
+    if let Err(e) = Content::load(Path::new("content")) {
+        panic!("{}", e);
+    }
+}
@@ -0,0 +1,137 @@
+//! Non-player characters to talk to. What they say is a tree of nodes defined in content files,
+//! while where each character is in a conversation is kept in the database.
+
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+use crate::items::Inventory;
+use crate::quests::QuestState;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Npc {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// The id of the location the character can always be found at. Characters without one wander
+    /// about, and are only met by chance, see [crate::world::RandomEncounter].
+    pub location: Option<String>,
+    /// Id of the node every conversation begins at.
+    pub start: String,
+    pub nodes: Vec<Node>,
+}
+
+/// Something the character says, and what can be said in return.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Node {
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub choices: Vec<Choice>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Choice {
+    /// Link text shown to the player.
+    pub label: String,
+    /// Id of the node the conversation goes on with. Without one, the conversation ends.
+    pub next: Option<String>,
+    #[serde(default)]
+    pub conditions: Conditions,
+    #[serde(default)]
+    pub effects: Effects,
+}
+
+/// What a character needs for a choice to be offered. All of them must be met.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Conditions {
+    pub min_level: Option<i32>,
+    /// Id of an item the character must carry.
+    pub item: Option<String>,
+    /// Id of a quest the character must be in `quest_state` with.
+    pub quest: Option<String>,
+    pub quest_state: Option<QuestState>,
+}
+
+/// What happens when a choice is made.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Effects {
+    /// Id of a quest the character takes on.
+    pub start_quest: Option<String>,
+    pub give_item: Option<Gift>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gift {
+    pub item: String,
+    pub quantity: i32,
+}
+
+impl Conditions {
+    /// Whether a character at `level` carrying `inventory` meets the conditions. `quest_state` tells
+    /// where the character stands with a quest, given its id.
+    pub fn met(&self, level: i32, inventory: &Inventory, quest_state: impl Fn(&str) -> QuestState) -> bool {
+        self.min_level.is_none_or(|min_level| level >= min_level)
+            && self.item.as_ref().is_none_or(|item| inventory.quantity(item) > 0)
+            && match (&self.quest, self.quest_state) {
+                (Some(quest), Some(state)) => quest_state(quest) == state,
+                _ => true,
+            }
+    }
+}
+
+impl Npc {
+    pub fn node(&self, id: &str) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.id.is_empty() || !self.id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+            problems.push(format!("character id \"{}\" may only contain lowercase letters, digits and dashes", self.id));
+        }
+        if self.node(&self.start).is_none() {
+            problems.push(format!("character {} starts at node {}, which does not exist", self.id, self.start));
+        }
+        let mut seen = HashSet::new();
+        for node in &self.nodes {
+            if !seen.insert(&node.id) {
+                problems.push(format!("character {} has node {} more than once", self.id, node.id));
+            }
+            for choice in &node.choices {
+                if let Some(next) = choice.next.as_ref().filter(|next| self.node(next).is_none()) {
+                    problems.push(format!("node {} of character {} leads to node {}, which does not exist", node.id, self.id, next));
+                }
+                if choice.conditions.quest.is_some() != choice.conditions.quest_state.is_some() {
+                    problems.push(format!("node {} of character {} needs both a quest and a quest state", node.id, self.id));
+                }
+                if choice.effects.give_item.as_ref().is_some_and(|gift| gift.quantity < 1) {
+                    problems.push(format!("node {} of character {} must give at least one item", node.id, self.id));
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[test]
+fn test_conditions() {
+    let inventory = Inventory::new(vec![("key".to_owned(), 1)]);
+    let conditions = Conditions {
+        min_level: Some(2),
+        item: Some("key".to_owned()),
+        quest: Some("rats".to_owned()),
+        quest_state: Some(QuestState::Active),
+    };
+    assert!(conditions.met(2, &inventory, |_| QuestState::Active));
+    assert!(!conditions.met(1, &inventory, |_| QuestState::Active));
+    assert!(!conditions.met(2, &Inventory::default(), |_| QuestState::Active));
+    assert!(!conditions.met(2, &inventory, |_| QuestState::Done));
+    assert!(Conditions::default().met(1, &Inventory::default(), |_| QuestState::Unavailable));
+}
@@ -14,6 +14,8 @@ pub trait Humanize {
 }
 
 impl Humanize for Duration {
+    // Every case returns the same way, the last one included.
+    #[allow(clippy::needless_return)]
     fn humanize(&self) -> String {
         let years = self.as_secs() / SECONDS_IN_A_YEAR;
         if years == 1 { return "a year".to_owned(); }
@@ -46,6 +48,7 @@ impl Humanize for Duration {
 }
 
 #[test]
+#[allow(clippy::identity_op)]
 fn test_humanize() {
     assert_eq!(Duration::from_micros(17).humanize(), "less than a second");
     assert_eq!(Duration::from_millis(17).humanize(), "less than a second");
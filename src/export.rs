@@ -0,0 +1,210 @@
+//! A copy of everything kept about an account and its characters, for players to take with them,
+//! as JSON or as plain text.
+
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::accounts;
+use crate::items::{Equipment, Inventory};
+use crate::storage::{Account, Certificate, HistoryEntry, QuestProgress, User};
+
+#[derive(Debug)]
+pub struct Export {
+    pub account: Account,
+    pub certificates: Vec<Certificate>,
+    pub has_recovery_secret: bool,
+    pub characters: Vec<CharacterExport>,
+}
+
+#[derive(Debug)]
+pub struct CharacterExport {
+    pub user: User,
+    pub equipment: Equipment,
+    pub inventory: Inventory,
+    pub quests: Vec<QuestProgress>,
+    /// Everything that happened to the character, newest first.
+    pub history: Vec<HistoryEntry>,
+}
+
+/// The JSON document, which keeps its shape when what is stored changes.
+#[derive(Serialize)]
+struct AccountDocument<'a> {
+    account: i32,
+    exported_at: String,
+    recovery_secret: bool,
+    certificates: Vec<CertificateDocument>,
+    characters: Vec<CharacterDocument<'a>>,
+}
+
+#[derive(Serialize)]
+struct CertificateDocument {
+    fingerprint: String,
+    linked_at: String,
+}
+
+#[derive(Serialize)]
+struct CharacterDocument<'a> {
+    id: i32,
+    name: &'a str,
+    playing: bool,
+    level: i32,
+    xp: i32,
+    strength: i32,
+    agility: i32,
+    vitality: i32,
+    health: i32,
+    max_health: i32,
+    gold: i32,
+    location: &'a str,
+    last_inn: Option<&'a str>,
+    equipment: Vec<WornDocument<'a>>,
+    inventory: Vec<CarriedDocument<'a>>,
+    quests: Vec<QuestDocument<'a>>,
+    history: Vec<HistoryDocument<'a>>,
+}
+
+#[derive(Serialize)]
+struct WornDocument<'a> {
+    slot: &'static str,
+    item: &'a str,
+}
+
+#[derive(Serialize)]
+struct CarriedDocument<'a> {
+    item: &'a str,
+    quantity: i32,
+}
+
+#[derive(Serialize)]
+struct QuestDocument<'a> {
+    quest: &'a str,
+    progress: &'a [i32],
+    completed: bool,
+}
+
+#[derive(Serialize)]
+struct HistoryDocument<'a> {
+    happened_at: String,
+    kind: &'a str,
+    description: &'a str,
+}
+
+impl Export {
+    pub fn to_json(&self) -> String {
+        let document = AccountDocument {
+            account: self.account.id,
+            exported_at: timestamp(SystemTime::now()),
+            recovery_secret: self.has_recovery_secret,
+            certificates: self.certificates.iter().map(|certificate| CertificateDocument {
+                fingerprint: accounts::fingerprint_hex(&certificate.fingerprint),
+                linked_at: timestamp(certificate.linked_at),
+            }).collect(),
+            characters: self.characters.iter().map(|character| self.character_document(character)).collect(),
+        };
+        // Only maps with keys that are not strings fail to serialize, and there are none.
+        serde_json::to_string(&document).expect("Failed to serialize export")
+    }
+
+    fn character_document<'a>(&self, character: &'a CharacterExport) -> CharacterDocument<'a> {
+        let user = &character.user;
+        CharacterDocument {
+            id: user.id,
+            name: &user.name,
+            playing: self.account.active_user == Some(user.id),
+            level: user.level,
+            xp: user.xp,
+            strength: user.attributes.strength,
+            agility: user.attributes.agility,
+            vitality: user.attributes.vitality,
+            health: user.health,
+            max_health: user.max_health,
+            gold: user.gold,
+            location: &user.location,
+            last_inn: user.last_inn.as_deref(),
+            equipment: character.equipment.slots().iter()
+                .map(|(slot, item)| WornDocument { slot: slot.segment(), item })
+                .collect(),
+            inventory: character.inventory.entries().iter()
+                .map(|(item, quantity)| CarriedDocument { item, quantity: *quantity })
+                .collect(),
+            quests: character.quests.iter()
+                .map(|progress| QuestDocument { quest: &progress.quest, progress: &progress.progress, completed: progress.completed })
+                .collect(),
+            history: character.history.iter()
+                .map(|entry| HistoryDocument { happened_at: timestamp(entry.happened_at), kind: &entry.kind, description: &entry.description })
+                .collect(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Namushul account {}\nExported at {}\n\nCertificates:\n", self.account.id, timestamp(SystemTime::now()));
+        for certificate in &self.certificates {
+            text.push_str(&format!("* {}, linked at {}\n", accounts::fingerprint_hex(&certificate.fingerprint), timestamp(certificate.linked_at)));
+        }
+        text.push_str(&format!("Recovery secret: {}\n", if self.has_recovery_secret { "set" } else { "not set" }));
+        for character in &self.characters {
+            let user = &character.user;
+            let attributes = &user.attributes;
+            let playing = if self.account.active_user == Some(user.id) { " (playing)" } else { "" };
+            text.push_str(&format!("\nCharacter #{}: {}{}\n", user.id, user.name, playing));
+            text.push_str(&format!("Level {}, {} XP\n", user.level, user.xp));
+            text.push_str(&format!("Strength {}, agility {}, vitality {}\n", attributes.strength, attributes.agility, attributes.vitality));
+            text.push_str(&format!("HP {}/{}, {} gold\n", user.health, user.max_health, user.gold));
+            text.push_str(&format!("Location: {}\n", user.location));
+            if let Some(inn) = &user.last_inn {
+                text.push_str(&format!("Last inn: {}\n", inn));
+            }
+            for (slot, item) in character.equipment.slots() {
+                text.push_str(&format!("Wears {} ({})\n", item, slot.segment()));
+            }
+            for (item, quantity) in character.inventory.entries() {
+                text.push_str(&format!("Carries {} × {}\n", item, quantity));
+            }
+            for progress in &character.quests {
+                let counts = progress.progress.iter().map(|count| count.to_string()).collect::<Vec<_>>().join(", ");
+                let state = if progress.completed { "completed" } else { "active" };
+                text.push_str(&format!("Quest {}: {} ({})\n", progress.quest, state, counts));
+            }
+            for entry in &character.history {
+                text.push_str(&format!("{} {}: {}\n", timestamp(entry.happened_at), entry.kind, entry.description));
+            }
+        }
+        text
+    }
+}
+
+/// `time` in UTC, such as `2023-11-14T22:13:20Z`.
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+#[test]
+fn test_to_json() {
+    use std::time::Duration;
+
+    let user = User { name: "\"Tess\"\n".to_owned(), ..crate::progression::test_user(2, 15) };
+    let export = Export {
+        account: Account { id: 3, active_user: Some(user.id) },
+        certificates: vec![],
+        has_recovery_secret: false,
+        characters: vec![CharacterExport {
+            user,
+            equipment: Equipment::default(),
+            inventory: Inventory::new(vec![("herb".to_owned(), 2)]),
+            quests: vec![],
+            history: vec![HistoryEntry {
+                happened_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                kind: "level-up".to_owned(),
+                description: "Reached level 2.".to_owned(),
+            }],
+        }],
+    };
+    let json: serde_json::Value = serde_json::from_str(&export.to_json()).unwrap();
+    let character = &json["characters"][0];
+    assert_eq!(json["account"], 3);
+    assert_eq!(character["name"], "\"Tess\"\n");
+    assert_eq!(character["playing"], true);
+    assert_eq!(character["inventory"][0]["quantity"], 2);
+    assert_eq!(character["history"][0]["happened_at"], "2023-11-14T22:13:20Z");
+}
@@ -6,6 +6,7 @@ use std::convert::TryInto;
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::Arc;
 use std::thread;
 
@@ -15,11 +16,13 @@ use percent_encoding::percent_decode;
 use url::Url;
 
 use crate::application::{Request, Application};
-use crate::Args;
+use crate::config::Config;
+use crate::content::Content;
+use crate::random::Random;
 use crate::response::Response;
 use std::time::Instant;
 
-fn make_acceptor(private_key_path: String, certificates_path: String) -> Arc<SslAcceptor> {
+pub fn make_acceptor(private_key_path: &Path, certificates_path: &Path) -> Arc<SslAcceptor> {
     let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
     acceptor.set_min_proto_version(Some(SslVersion::TLS1_2)).unwrap();
     acceptor.set_verify_callback(SslVerifyMode::PEER, |_ver, store| {
@@ -34,11 +37,9 @@ fn make_acceptor(private_key_path: String, certificates_path: String) -> Arc<Ssl
     Arc::new(acceptor.build())
 }
 
-pub fn read_request(stream: &mut SslStream<TcpStream>) -> Result<Url, Response> {
-    const MAX_URL_LENGTH: usize = 1024;
+pub fn read_request(stream: &mut SslStream<TcpStream>, max_url_length: usize) -> Result<Url, Response> {
     const CRLF_LENGTH: usize = 2;
-    const MAX_REQUEST_LENGTH: usize = MAX_URL_LENGTH + CRLF_LENGTH;
-    let mut buffer = [0; MAX_REQUEST_LENGTH];
+    let mut buffer = vec![0; max_url_length + CRLF_LENGTH];
 
     let mut total_count = stream.read(&mut buffer).unwrap();
     let mut closed = total_count == 0;
@@ -72,9 +73,10 @@ pub fn read_request(stream: &mut SslStream<TcpStream>) -> Result<Url, Response>
     }
 }
 
-pub fn handle_connection(stream: &mut SslStream<TcpStream>, start_time: Instant) -> Response {
-    let server = Application::new(start_time);
-    match read_request(stream) {
+pub fn handle_connection(stream: &mut SslStream<TcpStream>, start_time: Instant, config: Arc<Config>, content: Arc<Content>, random: Random) -> Response {
+    let max_url_length = config.transport.max_url_length;
+    let server = Application::new(start_time, config, content, random);
+    match read_request(stream, max_url_length) {
         Ok(url) => {
             let peer_fingerprint: Option<[u8; 32]> = match stream.ssl().peer_certificate() {
                 Some(peer_certificate) => {
@@ -101,20 +103,29 @@ pub fn handle_connection(stream: &mut SslStream<TcpStream>, start_time: Instant)
     }
 }
 
-pub fn run(args: Args) {
-    let Args { address, private_key_path, certificates_path } = args;
-    let listener = TcpListener::bind(address).unwrap();
+pub fn run(config: Config, content: Content) {
+    let transport = &config.transport;
+    let listener = TcpListener::bind(transport.address).unwrap();
+    let (certificates_path, private_key_path) = transport.tls_paths().unwrap();
     let acceptor = make_acceptor(private_key_path, certificates_path);
+    let config = Arc::new(config);
+    let content = Arc::new(content);
     let start_time = Instant::now();
+    let seed = config.game.random_seed().unwrap_or_else(rand::random);
+    eprintln!("Rolling dice with seed {}", seed);
+    let random = Random::Seeded(seed);
 
     loop {
         match listener.accept() {
             Ok((stream, address)) => {
                 eprintln!("New connection: {}", address);
                 let acceptor = acceptor.clone();
+                let config = config.clone();
+                let content = content.clone();
+                let random = random.clone();
                 thread::spawn(move || {
                     let mut stream = acceptor.accept(stream).unwrap();
-                    let response = handle_connection(&mut stream, start_time);
+                    let response = handle_connection(&mut stream, start_time, config, content, random);
                     stream.write_all(response.as_bytes()).unwrap();
                     stream.shutdown().unwrap();
 
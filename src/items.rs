@@ -0,0 +1,428 @@
+//! Things characters carry around. Items are defined in content files, while what each character
+//! carries is kept in their [Inventory], and what they wear in their [Equipment].
+
+use std::cmp::{max, min};
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Item {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub kind: ItemKind,
+    /// How many of the item fit into a single inventory slot.
+    pub stack_size: i32,
+    /// What the item is worth, in gold.
+    pub value: i32,
+    /// Health restored by using the item. Only consumables have an effect.
+    #[serde(default)]
+    pub heal: i32,
+    /// Where equipment is worn.
+    pub slot: Option<Slot>,
+    /// What equipment adds to the stats of whoever wears it.
+    #[serde(default)]
+    pub bonus: Bonus,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bonus {
+    #[serde(default)]
+    pub attack: i32,
+    #[serde(default)]
+    pub defense: i32,
+    /// Added to maximum health.
+    #[serde(default)]
+    pub health: i32,
+}
+
+impl std::ops::Add for Bonus {
+    type Output = Bonus;
+
+    fn add(self, other: Bonus) -> Bonus {
+        Bonus {
+            attack: self.attack + other.attack,
+            defense: self.defense + other.defense,
+            health: self.health + other.health,
+        }
+    }
+}
+
+impl std::ops::Sub for Bonus {
+    type Output = Bonus;
+
+    fn sub(self, other: Bonus) -> Bonus {
+        Bonus {
+            attack: self.attack - other.attack,
+            defense: self.defense - other.defense,
+            health: self.health - other.health,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Slot {
+    Head,
+    Body,
+    Weapon,
+    Offhand,
+    Ring,
+}
+
+impl Slot {
+    pub const ALL: [Slot; 5] = [Slot::Head, Slot::Body, Slot::Weapon, Slot::Offhand, Slot::Ring];
+
+    pub fn from_segment(segment: &str) -> Option<Slot> {
+        Slot::ALL.iter().copied().find(|slot| slot.segment() == segment)
+    }
+
+    pub fn segment(self) -> &'static str {
+        match self {
+            Slot::Head => "head",
+            Slot::Body => "body",
+            Slot::Weapon => "weapon",
+            Slot::Offhand => "offhand",
+            Slot::Ring => "ring",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Slot::Head => "Head",
+            Slot::Body => "Body",
+            Slot::Weapon => "Weapon",
+            Slot::Offhand => "Off hand",
+            Slot::Ring => "Ring",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemKind {
+    /// Used up to get its effect.
+    Consumable,
+    /// Monster parts and the like, only good for selling.
+    Material,
+    /// Opens the way to somewhere.
+    Key,
+    /// Worn in a slot to improve stats.
+    Equipment,
+}
+
+impl ItemKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            ItemKind::Consumable => "consumable",
+            ItemKind::Material => "material",
+            ItemKind::Key => "key",
+            ItemKind::Equipment => "equipment",
+        }
+    }
+}
+
+impl Item {
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.id.is_empty() || !self.id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+            problems.push(format!("item id \"{}\" may only contain lowercase letters, digits and dashes", self.id));
+        }
+        if self.stack_size < 1 {
+            problems.push(format!("item {} must have a stack size of at least 1", self.id));
+        }
+        if self.value < 0 {
+            problems.push(format!("item {} must not have a negative value", self.id));
+        }
+        match self.kind {
+            ItemKind::Consumable if self.heal <= 0 => problems.push(format!("consumable {} has no effect", self.id)),
+            ItemKind::Consumable => {}
+            _ if self.heal != 0 => problems.push(format!("item {} heals, but is not a consumable", self.id)),
+            _ => {}
+        }
+        match (self.kind, self.slot) {
+            (ItemKind::Equipment, None) => problems.push(format!("equipment {} has no slot", self.id)),
+            (ItemKind::Equipment, Some(_)) => {}
+            (_, Some(_)) => problems.push(format!("item {} has a slot, but is not equipment", self.id)),
+            (_, None) if self.bonus != Bonus::default() => problems.push(format!("item {} has a bonus, but is not equipment", self.id)),
+            (_, None) => {}
+        }
+        if self.bonus.health < 0 {
+            problems.push(format!("equipment {} must not lower maximum health", self.id));
+        }
+        problems
+    }
+}
+
+pub fn find<'a>(items: &'a [Item], id: &str) -> Option<&'a Item> {
+    items.iter().find(|item| item.id == id)
+}
+
+/// Slots taken up by `quantity` of an item that stacks up to `stack_size`.
+pub fn stacks(quantity: i32, stack_size: i32) -> i32 {
+    (quantity + stack_size - 1) / stack_size
+}
+
+/// The items a character carries, and how many of each.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Inventory {
+    entries: Vec<(String, i32)>,
+}
+
+impl Inventory {
+    pub fn new(entries: Vec<(String, i32)>) -> Inventory {
+        Inventory { entries }
+    }
+
+    /// Item ids and quantities, none of them zero.
+    pub fn entries(&self) -> &[(String, i32)] {
+        &self.entries
+    }
+
+    pub fn quantity(&self, id: &str) -> i32 {
+        self.entries.iter().find(|(item, _)| item == id).map_or(0, |&(_, quantity)| quantity)
+    }
+
+    pub fn item_ids(&self) -> Vec<String> {
+        self.entries.iter().map(|(item, _)| item.clone()).collect()
+    }
+
+    /// Slots in use. Items that no longer exist in the content take up a single slot.
+    pub fn slots_used(&self, items: &[Item]) -> i32 {
+        self.entries.iter()
+            .map(|(id, quantity)| find(items, id).map_or(1, |item| stacks(*quantity, item.stack_size)))
+            .sum()
+    }
+
+    /// Adds as many of `item`, up to `quantity`, as fit into `capacity` slots. Returns how many
+    /// were added.
+    pub fn add(&mut self, items: &[Item], item: &Item, quantity: i32, capacity: i32) -> i32 {
+        let carried = self.quantity(&item.id);
+        let used_by_others = self.slots_used(items) - stacks(carried, item.stack_size);
+        let room = max(0, (capacity - used_by_others) * item.stack_size - carried);
+        let added = min(quantity, room);
+        if added <= 0 {
+            return 0;
+        }
+        match self.entries.iter_mut().find(|(id, _)| *id == item.id) {
+            Some((_, carried)) => *carried += added,
+            None => self.entries.push((item.id.clone(), added)),
+        }
+        added
+    }
+
+    /// Takes `quantity` of an item out. Returns `false`, changing nothing, when there are not
+    /// that many.
+    pub fn remove(&mut self, id: &str, quantity: i32) -> bool {
+        let position = match self.entries.iter().position(|(item, carried)| item == id && *carried >= quantity) {
+            Some(position) => position,
+            None => return false,
+        };
+        self.entries[position].1 -= quantity;
+        if self.entries[position].1 == 0 {
+            self.entries.remove(position);
+        }
+        true
+    }
+}
+
+/// Why a piece of equipment cannot be put on or taken off.
+#[derive(Debug, PartialEq)]
+pub enum EquipError {
+    NotEquipment,
+    NotCarried,
+    /// Nothing is worn in the slot.
+    Empty,
+    /// There is no room in the inventory for what is taken off.
+    NoRoom,
+}
+
+/// What a character wears: the id of an item for each slot that is not empty.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Equipment {
+    slots: Vec<(Slot, String)>,
+}
+
+impl Equipment {
+    pub fn new(slots: Vec<(Slot, String)>) -> Equipment {
+        Equipment { slots }
+    }
+
+    pub fn get(&self, slot: Slot) -> Option<&str> {
+        self.slots.iter().find(|(s, _)| *s == slot).map(|(_, item)| item.as_str())
+    }
+
+    pub fn slots(&self) -> &[(Slot, String)] {
+        &self.slots
+    }
+
+    /// What everything worn adds up to. Items that no longer exist add nothing.
+    pub fn bonus(&self, items: &[Item]) -> Bonus {
+        self.slots.iter()
+            .filter_map(|(_, id)| find(items, id))
+            .fold(Bonus::default(), |total, item| total + item.bonus)
+    }
+
+    /// Moves `item` from `inventory` into its slot, and whatever was worn there into `inventory`.
+    pub fn equip(&mut self, items: &[Item], inventory: &mut Inventory, item: &Item, capacity: i32) -> Result<(), EquipError> {
+        let slot = match (item.kind, item.slot) {
+            (ItemKind::Equipment, Some(slot)) => slot,
+            _ => return Err(EquipError::NotEquipment),
+        };
+        // Work on a copy, so that nothing changes when the swap fails half way.
+        let mut changed = inventory.clone();
+        if !changed.remove(&item.id, 1) {
+            return Err(EquipError::NotCarried);
+        }
+        if self.get(slot).is_some() {
+            self.unequip(items, &mut changed, slot, capacity)?;
+        }
+        self.slots.push((slot, item.id.clone()));
+        *inventory = changed;
+        Ok(())
+    }
+
+    /// Moves what is worn in `slot` into `inventory`. Returns the id of the item.
+    pub fn unequip(&mut self, items: &[Item], inventory: &mut Inventory, slot: Slot, capacity: i32) -> Result<String, EquipError> {
+        let position = self.slots.iter().position(|(s, _)| *s == slot).ok_or(EquipError::Empty)?;
+        let id = &self.slots[position].1;
+        // Items that no longer exist are simply thrown away.
+        if let Some(item) = find(items, id) {
+            if inventory.add(items, item, 1, capacity) == 0 {
+                return Err(EquipError::NoRoom);
+            }
+        }
+        Ok(self.slots.remove(position).1)
+    }
+}
+
+#[cfg(test)]
+pub fn test_items() -> Vec<Item> {
+    let item = |id: &str, kind, stack_size| Item {
+        id: id.to_owned(),
+        name: id.to_owned(),
+        description: String::new(),
+        kind,
+        stack_size,
+        value: 1,
+        heal: if kind == ItemKind::Consumable { 5 } else { 0 },
+        slot: None,
+        bonus: Bonus::default(),
+    };
+    let equipment = |id: &str, slot, bonus| Item { slot: Some(slot), bonus, ..item(id, ItemKind::Equipment, 1) };
+    vec![
+        item("herb", ItemKind::Consumable, 5),
+        item("tail", ItemKind::Material, 10),
+        item("key", ItemKind::Key, 1),
+        equipment("sword", Slot::Weapon, Bonus { attack: 2, defense: 0, health: 0 }),
+        equipment("axe", Slot::Weapon, Bonus { attack: 3, defense: -1, health: 0 }),
+        equipment("amulet", Slot::Ring, Bonus { attack: 0, defense: 0, health: 5 }),
+    ]
+}
+
+#[test]
+fn test_stacking() {
+    assert_eq!(stacks(0, 5), 0);
+    assert_eq!(stacks(1, 5), 1);
+    assert_eq!(stacks(5, 5), 1);
+    assert_eq!(stacks(6, 5), 2);
+
+    let items = test_items();
+    let mut inventory = Inventory::default();
+    assert_eq!(inventory.add(&items, &items[0], 7, 10), 7);
+    assert_eq!(inventory.add(&items, &items[0], 2, 10), 2);
+    assert_eq!(inventory.quantity("herb"), 9);
+    assert_eq!(inventory.slots_used(&items), 2);
+    assert_eq!(inventory.add(&items, &items[1], 1, 10), 1);
+    assert_eq!(inventory.slots_used(&items), 3);
+    assert_eq!(inventory.entries(), &[("herb".to_owned(), 9), ("tail".to_owned(), 1)]);
+}
+
+#[test]
+fn test_capacity() {
+    let items = test_items();
+    let mut inventory = Inventory::default();
+    assert_eq!(inventory.add(&items, &items[2], 3, 3), 3);
+    assert_eq!(inventory.add(&items, &items[0], 1, 3), 0);
+    assert!(inventory.remove("key", 1));
+
+    // The last free slot fits a whole stack, on top of what fills up existing stacks.
+    assert_eq!(inventory.add(&items, &items[0], 3, 3), 3);
+    assert_eq!(inventory.add(&items, &items[0], 10, 3), 2);
+    assert_eq!(inventory.quantity("herb"), 5);
+    assert_eq!(inventory.slots_used(&items), 3);
+
+    // Items removed from the content still take up room.
+    let mut inventory = Inventory::new(vec![("gone".to_owned(), 40)]);
+    assert_eq!(inventory.add(&items, &items[1], 30, 2), 10);
+}
+
+#[test]
+fn test_remove() {
+    let items = test_items();
+    let mut inventory = Inventory::default();
+    inventory.add(&items, &items[1], 3, 10);
+    assert!(!inventory.remove("tail", 4));
+    assert!(!inventory.remove("herb", 1));
+    assert!(inventory.remove("tail", 2));
+    assert!(inventory.remove("tail", 1));
+    assert_eq!(inventory, Inventory::default());
+}
+
+#[test]
+fn test_validate_item() {
+    let mut items = test_items();
+    assert!(items.iter().all(|item| item.validate().is_empty()));
+    items[0].heal = 0;
+    items[1].heal = 2;
+    items[2].stack_size = 0;
+    items[3].slot = None;
+    items[4].kind = ItemKind::Material;
+    assert_eq!(items.iter().map(|item| item.validate().len()).sum::<usize>(), 5);
+}
+
+#[test]
+fn test_equip() {
+    let items = test_items();
+    let mut inventory = Inventory::default();
+    let mut equipment = Equipment::default();
+    for item in &items[3..] {
+        inventory.add(&items, item, 1, 3);
+    }
+
+    assert_eq!(equipment.equip(&items, &mut inventory, &items[0], 3), Err(EquipError::NotEquipment));
+    assert_eq!(equipment.equip(&items, &mut inventory, &items[3], 3), Ok(()));
+    assert_eq!(equipment.equip(&items, &mut inventory, &items[3], 3), Err(EquipError::NotCarried));
+    assert_eq!(equipment.equip(&items, &mut inventory, &items[5], 3), Ok(()));
+    assert_eq!(equipment.bonus(&items), Bonus { attack: 2, defense: 0, health: 5 });
+    assert_eq!(inventory.entries(), &[("axe".to_owned(), 1)]);
+
+    // Swapping puts the sword back into the inventory.
+    assert_eq!(equipment.equip(&items, &mut inventory, &items[4], 3), Ok(()));
+    assert_eq!(equipment.get(Slot::Weapon), Some("axe"));
+    assert_eq!(inventory.entries(), &[("sword".to_owned(), 1)]);
+    assert_eq!(equipment.bonus(&items), Bonus { attack: 3, defense: -1, health: 5 });
+
+    assert_eq!(equipment.unequip(&items, &mut inventory, Slot::Ring, 3), Ok("amulet".to_owned()));
+    assert_eq!(equipment.unequip(&items, &mut inventory, Slot::Ring, 3), Err(EquipError::Empty));
+    assert_eq!(equipment.slots().len(), 1);
+}
+
+#[test]
+fn test_unequip_without_room() {
+    let items = test_items();
+    let mut inventory = Inventory::default();
+    let mut equipment = Equipment::default();
+    inventory.add(&items, &items[3], 1, 1);
+    equipment.equip(&items, &mut inventory, &items[3], 1).unwrap();
+    inventory.add(&items, &items[1], 1, 1);
+
+    assert_eq!(equipment.unequip(&items, &mut inventory, Slot::Weapon, 1), Err(EquipError::NoRoom));
+    // A swap that cannot put the old weapon anywhere changes nothing.
+    let mut inventory = Inventory::new(vec![("axe".to_owned(), 1)]);
+    assert_eq!(equipment.equip(&items, &mut inventory, &items[4], 0), Err(EquipError::NoRoom));
+    assert_eq!(inventory.entries(), &[("axe".to_owned(), 1)]);
+    assert_eq!(equipment.get(Slot::Weapon), Some("sword"));
+}
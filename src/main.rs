@@ -1,30 +1,134 @@
+use std::error;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
 
 use structopt::StructOpt;
 
+use crate::admin::{FetchArgs, GenCertArgs, UserCommand};
+use crate::config::Config;
+use crate::content::Content;
+use crate::storage::Storage;
+
+mod accounts;
+mod admin;
 mod application;
+mod attributes;
+mod certificate;
+mod client;
+mod combat;
+mod config;
+mod content;
+mod dialogue;
+mod export;
 mod gemini;
+mod items;
+mod progression;
+mod quests;
+mod random;
 mod response;
+mod shops;
 mod storage;
 mod duration;
+mod world;
 
 #[derive(StructOpt, Debug)]
 #[structopt()]
 pub struct Args {
-    /// Path to a certificate file encoded in PEM format for TLS connections
-    #[structopt()]
-    certificates_path: String,
+    /// Path to a TOML configuration file [default: namushul.toml, if it exists]
+    #[structopt(short, long, env = "NAMUSHUL_CONFIG", global = true)]
+    config: Option<PathBuf>,
 
-    /// Path to a PKCS 8 or RSA keyfile encoded in PEM format for TLS connections
-    #[structopt()]
-    private_key_path: String,
+    /// Override a configuration value, e.g. `--set game.starting_health=12`
+    #[structopt(long = "set", number_of_values = 1, global = true, parse(try_from_str = config::parse_key_value))]
+    overrides: Vec<(String, String)>,
 
-    /// Bind server to this address
-    #[structopt(default_value = "0.0.0.0:1965", short, long)]
-    address: SocketAddr,
+    #[structopt(subcommand)]
+    command: Command,
 }
 
-fn main() {
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Run the gemini server
+    Serve {
+        /// Path to a certificate file encoded in PEM format for TLS connections
+        #[structopt()]
+        certificates_path: Option<String>,
+
+        /// Path to a PKCS 8 or RSA keyfile encoded in PEM format for TLS connections
+        #[structopt()]
+        private_key_path: Option<String>,
+
+        /// Bind server to this address [default: 0.0.0.0:1965]
+        #[structopt(short, long)]
+        address: Option<SocketAddr>,
+    },
+    /// Apply pending database migrations
+    Migrate,
+    /// Inspect and fix characters
+    User(UserCommand),
+    /// Show how many characters there are, and where they are
+    Stats,
+    /// Validate the configuration and print the effective values
+    CheckConfig,
+    /// Generate a self-signed certificate and private key for the server
+    GenCert(GenCertArgs),
+    /// Request a gemini URL and print the response
+    Fetch(FetchArgs),
+}
+
+impl Args {
+    /// All configuration values given on the command line, the dedicated flags taking precedence.
+    fn overrides(&self) -> Vec<(String, String)> {
+        let mut overrides = self.overrides.clone();
+        if let Command::Serve { certificates_path, private_key_path, address } = &self.command {
+            if let Some(path) = certificates_path {
+                overrides.push(("transport.certificates_path".to_owned(), path.clone()));
+            }
+            if let Some(path) = private_key_path {
+                overrides.push(("transport.private_key_path".to_owned(), path.clone()));
+            }
+            if let Some(address) = address {
+                overrides.push(("transport.address".to_owned(), address.to_string()));
+            }
+        }
+        overrides
+    }
+}
+
+fn serve(config: Config) -> Result<(), Box<dyn error::Error>> {
+    config.transport.tls_paths()?;
+    let content = Content::load(&config.game.content_path)?;
+    if config.storage.migrate_on_startup {
+        for migration in Storage::new(&config.storage)?.migrate()? {
+            eprintln!("Applied migration {} {}", migration.version, migration.name);
+        }
+    }
     eprintln!("Starting...");
-    gemini::run(Args::from_args());
+    gemini::run(config, content);
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    let config = match Config::load(args.config.as_deref(), &args.overrides()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let result = match args.command {
+        Command::Serve { .. } => serve(config),
+        Command::Migrate => admin::migrate(&config),
+        Command::User(command) => admin::user(&config, command),
+        Command::Stats => admin::stats(&config),
+        Command::CheckConfig => admin::check_config(&config),
+        Command::GenCert(args) => admin::gen_cert(&config, args),
+        Command::Fetch(args) => admin::fetch(args),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,197 @@
+//! Experience, levels and the stats derived from them. Characters gain experience by winning
+//! fights, and go up a level each time their experience reaches the next step of the level curve
+//! in the configuration. Health regenerates over time.
+
+use std::cmp::{max, min};
+use std::time::{Duration, SystemTime};
+
+use crate::attributes::Attributes;
+use crate::combat::Stats;
+use crate::config::GameConfig;
+use crate::storage::User;
+
+/// The level a character with `xp` experience is at. `curve` holds the experience needed for
+/// level 2, level 3 and so on, and its length decides the highest level.
+pub fn level_for(curve: &[i32], xp: i32) -> i32 {
+    1 + curve.iter().take_while(|&&needed| xp >= needed).count() as i32
+}
+
+/// The experience needed to reach `level`, or `None` past the highest level.
+pub fn xp_for(curve: &[i32], level: i32) -> Option<i32> {
+    match level {
+        i32::MIN..=1 => Some(0),
+        _ => curve.get(level as usize - 2).copied(),
+    }
+}
+
+/// Fighting strength of a character at `level`.
+pub fn stats(config: &GameConfig, level: i32, attributes: &Attributes) -> Stats {
+    Stats {
+        attack: config.base_attack + attributes.strength + (level - 1) * config.level_attack,
+        defense: config.base_defense + attributes.agility / 2 + (level - 1) * config.level_defense,
+    }
+}
+
+/// Maximum health of a character at `level`, before what they wear. It is stored with the
+/// character, so that it only changes on a level up or a change of equipment, and not whenever the
+/// configuration does.
+pub fn max_health(config: &GameConfig, level: i32, attributes: &Attributes) -> i32 {
+    config.starting_health + attributes.vitality * config.vitality_health + (level - 1) * config.level_health
+}
+
+/// Gives `user` `xp` more experience. Each level gained raises their maximum health, and a level
+/// up restores their health fully.
+pub fn award_xp(config: &GameConfig, user: User, xp: i32) -> User {
+    let xp = user.xp + xp;
+    let level = max(user.level, level_for(&config.level_xp, xp));
+    if level == user.level {
+        return User { xp, ..user };
+    }
+    let max_health = user.max_health + (level - user.level) * config.level_health;
+    User { xp, level, max_health, health: max_health, ..user }
+}
+
+/// Works out the user's maximum health afresh, with `bonus` from what they wear, as when putting
+/// on or taking off equipment. Adding up changes instead would drift whenever an item's bonus
+/// changes while it is worn. Health is kept within the new maximum.
+pub fn update_max_health(config: &GameConfig, user: &mut User, bonus: i32) {
+    user.max_health = max(1, max_health(config, user.level, &user.attributes) + bonus);
+    user.health = min(user.health, user.max_health);
+}
+
+/// Adds the health `user` regenerated by `now`, a point for every `interval` since
+/// `health_updated_at`, up to their maximum health. Characters out cold do not regenerate.
+pub fn regenerate(user: &mut User, interval: Duration, now: SystemTime) {
+    if user.health <= 0 {
+        return;
+    }
+    let missing = max(0, user.max_health - user.health);
+    let elapsed = now.duration_since(user.health_updated_at).unwrap_or_default();
+    let points = min(missing as u128, elapsed.as_millis() / interval.as_millis().max(1)) as i32;
+    if points == missing {
+        // Time spent at full health does not count towards regenerating after the next fight.
+        user.health = max(user.health, user.max_health);
+        user.health_updated_at = now;
+    } else {
+        user.health += points;
+        user.health_updated_at += interval * points as u32;
+    }
+}
+
+/// How long until `user` has regenerated to full health, or `None` when they will not.
+pub fn full_in(user: &User, interval: Duration, now: SystemTime) -> Option<Duration> {
+    if user.health <= 0 || user.health >= user.max_health {
+        return None;
+    }
+    let full_at = user.health_updated_at + interval * (user.max_health - user.health) as u32;
+    Some(full_at.duration_since(now).unwrap_or_default())
+}
+
+/// The experience a knocked out character loses. It is a share of what they gained since their
+/// last level up, so that nobody ever loses a level.
+pub fn knockout_xp_loss(config: &GameConfig, user: &User) -> i32 {
+    let progress = user.xp - xp_for(&config.level_xp, user.level).unwrap_or(user.xp);
+    max(0, progress) * config.knockout_xp_percent / 100
+}
+
+#[cfg(test)]
+pub fn test_user(level: i32, xp: i32) -> User {
+    User {
+        id: 1,
+        name: "Tester".to_owned(),
+        level,
+        xp,
+        attributes: Attributes::default(),
+        max_health: 10,
+        health: 4,
+        health_updated_at: SystemTime::UNIX_EPOCH,
+        gold: 0,
+        location: "bastow".to_owned(),
+        last_inn: None,
+        actions: 0,
+    }
+}
+
+#[test]
+fn test_level_for() {
+    let curve = [10, 25, 50];
+    assert_eq!(level_for(&curve, 0), 1);
+    assert_eq!(level_for(&curve, 9), 1);
+    assert_eq!(level_for(&curve, 10), 2);
+    assert_eq!(level_for(&curve, 49), 3);
+    assert_eq!(level_for(&curve, 5000), 4);
+    assert_eq!(xp_for(&curve, 1), Some(0));
+    assert_eq!(xp_for(&curve, 3), Some(25));
+    assert_eq!(xp_for(&curve, 5), None);
+}
+
+#[test]
+fn test_award_xp() {
+    let config = GameConfig { level_xp: vec![10, 25, 50], level_health: 2, ..GameConfig::default() };
+
+    let user = award_xp(&config, test_user(1, 0), 9);
+    assert_eq!((user.level, user.xp, user.max_health, user.health), (1, 9, 10, 4));
+
+    // Gaining two levels at once raises maximum health twice.
+    let user = award_xp(&config, user, 20);
+    assert_eq!((user.level, user.xp, user.max_health, user.health), (3, 29, 14, 14));
+
+    assert_eq!(user.max_health, max_health(&config, user.level, &user.attributes));
+}
+
+#[test]
+fn test_derived_stats() {
+    let config = GameConfig { starting_health: 10, vitality_health: 2, level_health: 3, level_attack: 1, level_defense: 0, ..GameConfig::default() };
+    let attributes = Attributes { strength: 2, agility: 3, vitality: 1 };
+    assert_eq!(stats(&config, 1, &attributes), Stats { attack: config.base_attack + 2, defense: config.base_defense + 1 });
+    assert_eq!(stats(&config, 3, &attributes).attack, config.base_attack + 4);
+    assert_eq!(max_health(&config, 1, &attributes), 12);
+    assert_eq!(max_health(&config, 2, &attributes), 15);
+}
+
+#[test]
+fn test_knockout_xp_loss() {
+    let config = GameConfig { level_xp: vec![10, 30], knockout_xp_percent: 50, ..GameConfig::default() };
+    assert_eq!(knockout_xp_loss(&config, &test_user(1, 7)), 3);
+    assert_eq!(knockout_xp_loss(&config, &test_user(2, 10)), 0);
+    assert_eq!(knockout_xp_loss(&config, &test_user(2, 20)), 5);
+    assert_eq!(knockout_xp_loss(&config, &test_user(3, 100)), 35);
+    // Levels the curve no longer has are kept, along with their experience.
+    assert_eq!(knockout_xp_loss(&config, &test_user(5, 100)), 0);
+}
+
+#[test]
+fn test_update_max_health() {
+    let config = GameConfig { starting_health: 10, level_health: 2, ..GameConfig::default() };
+    let mut user = test_user(2, 0);
+    update_max_health(&config, &mut user, 5);
+    assert_eq!((user.health, user.max_health), (4, 17));
+    user.health = 17;
+    update_max_health(&config, &mut user, 0);
+    assert_eq!((user.health, user.max_health), (12, 12));
+    // A stored maximum that drifted, such as after a reset, is set right.
+    user.max_health = 3;
+    update_max_health(&config, &mut user, -20);
+    assert_eq!((user.health, user.max_health), (1, 1));
+}
+
+#[test]
+fn test_regenerate() {
+    let minute = Duration::from_secs(60);
+    let start = SystemTime::UNIX_EPOCH;
+    let mut user = test_user(1, 0);
+    assert_eq!(full_in(&user, minute, start + minute / 2), Some(minute * 11 / 2));
+
+    // Partial progress towards the next point is kept.
+    regenerate(&mut user, minute, start + minute * 5 / 2);
+    assert_eq!((user.health, user.health_updated_at), (6, start + minute * 2));
+    assert_eq!(full_in(&user, minute, start + minute * 5 / 2), Some(minute * 7 / 2));
+
+    regenerate(&mut user, minute, start + minute * 60);
+    assert_eq!((user.health, user.health_updated_at), (10, start + minute * 60));
+    assert_eq!(full_in(&user, minute, start + minute * 60), None);
+
+    let mut user = User { health: 0, ..test_user(1, 0) };
+    regenerate(&mut user, minute, start + minute * 60);
+    assert_eq!(user.health, 0);
+}
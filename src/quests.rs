@@ -0,0 +1,230 @@
+//! Quests give characters something to work towards. Quests are defined in content files, while
+//! how far each character got is kept in the database.
+
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+use crate::items::Inventory;
+use crate::storage::QuestProgress;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quest {
+    pub id: String,
+    pub name: String,
+    /// What the quest giver asks for.
+    pub description: String,
+    /// Who hands out the quest, and takes it back once it is done.
+    pub giver: String,
+    /// The id of the location the quest giver is at.
+    pub location: String,
+    /// What the quest giver says when the quest is done.
+    pub completion: String,
+    #[serde(default)]
+    pub requires: Prerequisites,
+    pub objectives: Vec<Objective>,
+    #[serde(default)]
+    pub rewards: Rewards,
+}
+
+/// What a character needs before they are offered a quest.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prerequisites {
+    pub min_level: Option<i32>,
+    /// Ids of quests that must have been completed.
+    #[serde(default)]
+    pub quests: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Objective {
+    /// Defeat `count` of a monster.
+    Kill { monster: String, count: i32 },
+    /// Travel to a location.
+    Reach { location: String },
+    /// Hand over `count` of an item to the quest giver.
+    Deliver { item: String, count: i32 },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rewards {
+    #[serde(default)]
+    pub xp: i32,
+    #[serde(default)]
+    pub gold: i32,
+    #[serde(default)]
+    pub items: Vec<RewardItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewardItem {
+    pub item: String,
+    pub quantity: i32,
+}
+
+/// Where a character stands with a quest.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuestState {
+    /// Not taken on, and the prerequisites are not met.
+    Unavailable,
+    /// Not taken on, but it could be.
+    Available,
+    /// Taken on, with objectives left to do.
+    Active,
+    /// Taken on, with every objective done, waiting to be handed in.
+    Done,
+    Completed,
+}
+
+/// Something a character did that may count towards a quest.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// Holds the id of the monster defeated.
+    Kill(&'a str),
+    /// Holds the id of the location reached.
+    Reach(&'a str),
+}
+
+impl Objective {
+    /// How far the objective has to get to be done.
+    pub fn target(&self) -> i32 {
+        match self {
+            Objective::Kill { count, .. } | Objective::Deliver { count, .. } => *count,
+            Objective::Reach { .. } => 1,
+        }
+    }
+
+    /// How far the objective got, given its stored `count`. Deliveries are counted in the
+    /// inventory rather than stored, so that items can be sold or used in the meantime.
+    pub fn progress(&self, count: i32, inventory: &Inventory) -> i32 {
+        match self {
+            Objective::Deliver { item, count } => inventory.quantity(item).min(*count),
+            _ => count.min(self.target()),
+        }
+    }
+
+    fn counts(&self, event: Event) -> bool {
+        match (self, event) {
+            (Objective::Kill { monster, .. }, Event::Kill(id)) => monster == id,
+            (Objective::Reach { location }, Event::Reach(id)) => location == id,
+            _ => false,
+        }
+    }
+}
+
+impl Quest {
+    /// Whether a character at `level` who completed the quests in `completed` is offered the quest.
+    pub fn available(&self, level: i32, completed: &[&str]) -> bool {
+        self.requires.min_level.is_none_or(|min_level| level >= min_level)
+            && self.requires.quests.iter().all(|id| completed.contains(&id.as_str()))
+    }
+
+    /// Where a character at `level`, who has taken on `quests` and carries `inventory`, stands with
+    /// the quest.
+    pub fn state(&self, level: i32, quests: &[QuestProgress], inventory: &Inventory) -> QuestState {
+        match quests.iter().find(|progress| progress.quest == self.id) {
+            Some(progress) if progress.completed => QuestState::Completed,
+            Some(progress) if self.done(&progress.progress, inventory) => QuestState::Done,
+            Some(_) => QuestState::Active,
+            None => {
+                let completed = quests.iter().filter(|progress| progress.completed).map(|progress| progress.quest.as_str()).collect::<Vec<_>>();
+                if self.available(level, &completed) { QuestState::Available } else { QuestState::Unavailable }
+            }
+        }
+    }
+
+    /// Counts `event` towards the objectives, one count for each stored in `counts`. Returns
+    /// whether anything changed.
+    pub fn record(&self, counts: &mut Vec<i32>, event: Event) -> bool {
+        counts.resize(self.objectives.len(), 0);
+        let mut changed = false;
+        for (objective, count) in self.objectives.iter().zip(counts.iter_mut()) {
+            if objective.counts(event) && *count < objective.target() {
+                *count += 1;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Whether every objective is done, so that the quest can be handed in.
+    pub fn done(&self, counts: &[i32], inventory: &Inventory) -> bool {
+        self.objectives.iter().enumerate()
+            .all(|(i, objective)| objective.progress(counts.get(i).copied().unwrap_or(0), inventory) >= objective.target())
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.id.is_empty() || !self.id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+            problems.push(format!("quest id \"{}\" may only contain lowercase letters, digits and dashes", self.id));
+        }
+        if self.objectives.is_empty() {
+            problems.push(format!("quest {} has no objectives", self.id));
+        }
+        if self.objectives.iter().any(|objective| objective.target() < 1) {
+            problems.push(format!("quest {} must ask for a count of at least 1", self.id));
+        }
+        if self.requires.quests.contains(&self.id) {
+            problems.push(format!("quest {} requires itself", self.id));
+        }
+        let rewards = &self.rewards;
+        if rewards.xp < 0 || rewards.gold < 0 || rewards.items.iter().any(|reward| reward.quantity < 1) {
+            problems.push(format!("quest {} must not have negative rewards", self.id));
+        }
+        let mut seen = HashSet::new();
+        for reward in rewards.items.iter().filter(|reward| !seen.insert(&reward.item)) {
+            problems.push(format!("quest {} rewards {} more than once", self.id, reward.item));
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+fn test_quest() -> Quest {
+    Quest {
+        id: "rats".to_owned(),
+        name: "Rats".to_owned(),
+        description: String::new(),
+        giver: String::new(),
+        location: "town".to_owned(),
+        completion: String::new(),
+        requires: Prerequisites { min_level: Some(2), quests: vec!["slimes".to_owned()] },
+        objectives: vec![
+            Objective::Kill { monster: "rat".to_owned(), count: 2 },
+            Objective::Reach { location: "cellar".to_owned() },
+            Objective::Deliver { item: "tail".to_owned(), count: 3 },
+        ],
+        rewards: Rewards::default(),
+    }
+}
+
+#[test]
+fn test_available() {
+    let quest = test_quest();
+    assert!(!quest.available(1, &["slimes"]));
+    assert!(!quest.available(2, &[]));
+    assert!(quest.available(2, &["slimes", "bats"]));
+}
+
+#[test]
+fn test_record() {
+    let quest = test_quest();
+    let mut inventory = Inventory::new(vec![("tail".to_owned(), 5)]);
+    let mut counts = vec![];
+    assert!(!quest.record(&mut counts, Event::Kill("slime")));
+    assert!(quest.record(&mut counts, Event::Kill("rat")));
+    assert!(quest.record(&mut counts, Event::Reach("cellar")));
+    assert!(!quest.done(&counts, &inventory));
+    assert!(quest.record(&mut counts, Event::Kill("rat")));
+    assert!(!quest.record(&mut counts, Event::Kill("rat")));
+    assert_eq!(counts, vec![2, 1, 0]);
+    assert!(quest.done(&counts, &inventory));
+    assert!(inventory.remove("tail", 3));
+    assert!(!quest.done(&counts, &inventory));
+}
@@ -0,0 +1,85 @@
+//! Where the randomness of the game comes from. Every request that rolls dice gets its own
+//! generator, derived from the seed, the user and the number of actions they have taken, so that a
+//! request can be replayed the same way to reproduce a bug.
+
+use rand::{Error, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+
+#[derive(Debug, Clone)]
+pub enum Random {
+    /// Generators derived from a seed.
+    Seeded(u64),
+    /// Every generator returns the same numbers, over and over, for tests.
+    #[cfg(test)]
+    Scripted(Vec<u64>),
+}
+
+/// The generator for a single request.
+#[derive(Debug)]
+pub enum Dice {
+    Seeded(Box<StdRng>),
+    #[cfg(test)]
+    Scripted { numbers: Vec<u64>, next: usize },
+}
+
+impl Random {
+    /// The generator for the `action`th action of the user with `user_id`.
+    pub fn dice(&self, user_id: i32, action: i64) -> Dice {
+        match self {
+            Random::Seeded(seed) => {
+                let mut bytes = [0; 32];
+                bytes[..8].copy_from_slice(&seed.to_le_bytes());
+                bytes[8..12].copy_from_slice(&user_id.to_le_bytes());
+                bytes[12..20].copy_from_slice(&action.to_le_bytes());
+                Dice::Seeded(Box::new(StdRng::from_seed(bytes)))
+            }
+            #[cfg(test)]
+            Random::Scripted(numbers) => Dice::Scripted { numbers: numbers.clone(), next: 0 },
+        }
+    }
+}
+
+impl RngCore for Dice {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Dice::Seeded(rng) => rng.next_u64(),
+            #[cfg(test)]
+            Dice::Scripted { numbers, next } => {
+                let number = numbers.get(*next % numbers.len().max(1)).copied().unwrap_or(0);
+                *next += 1;
+                number
+            }
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dice() {
+    let random = Random::Seeded(7);
+    let roll = |user_id, action| {
+        let mut dice = random.dice(user_id, action);
+        (0..4).map(|_| dice.next_u64()).collect::<Vec<_>>()
+    };
+    assert_eq!(roll(1, 1), roll(1, 1));
+    assert_ne!(roll(1, 1), roll(1, 2));
+    assert_ne!(roll(1, 1), roll(2, 1));
+
+    let mut dice = Random::Scripted(vec![3, 5]).dice(1, 1);
+    assert_eq!((dice.next_u64(), dice.next_u64(), dice.next_u32()), (3, 5, 3));
+}
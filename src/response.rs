@@ -17,6 +17,68 @@ impl MediaType {
             None => MediaType("text/gemini".to_owned())
         }
     }
+
+    pub fn json() -> Self {
+        MediaType("application/json".to_owned())
+    }
+
+    pub fn plain() -> Self {
+        MediaType("text/plain; charset=utf-8".to_owned())
+    }
+}
+
+/// The two digit status code at the start of every response header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Input = 10,
+    SensitiveInput = 11,
+    Success = 20,
+    RedirectTemporary = 30,
+    RedirectPermanent = 31,
+    TemporaryFailure = 40,
+    ServerUnavailable = 41,
+    CgiError = 42,
+    ProxyError = 43,
+    SlowDown = 44,
+    PermanentFailure = 50,
+    NotFound = 51,
+    Gone = 52,
+    ProxyRequestRefused = 53,
+    BadRequest = 59,
+    ClientCertificateRequired = 60,
+    CertificateNotAuthorized = 61,
+    CertificateNotValid = 62,
+}
+
+impl Status {
+    const ALL: [Status; 18] = [
+        Status::Input, Status::SensitiveInput, Status::Success, Status::RedirectTemporary,
+        Status::RedirectPermanent, Status::TemporaryFailure, Status::ServerUnavailable,
+        Status::CgiError, Status::ProxyError, Status::SlowDown, Status::PermanentFailure,
+        Status::NotFound, Status::Gone, Status::ProxyRequestRefused, Status::BadRequest,
+        Status::ClientCertificateRequired, Status::CertificateNotAuthorized,
+        Status::CertificateNotValid,
+    ];
+
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Interprets a status code. Codes that are not known are treated as the basic code of their
+    /// category, e.g. 57 as 50, as clients are required to do.
+    pub fn from_code(code: u8) -> Option<Status> {
+        Status::ALL.iter().copied().find(|status| status.code() == code)
+            .or_else(|| Status::ALL.iter().copied().find(|status| status.code() == code - code % 10))
+    }
+
+    /// Whether the response has a body, which is only the case for [Status::Success].
+    pub fn has_body(self) -> bool {
+        self == Status::Success
+    }
+
+    pub fn is_redirect(self) -> bool {
+        matches!(self, Status::RedirectTemporary | Status::RedirectPermanent)
+    }
 }
 
 pub struct Response(String);
@@ -36,13 +98,13 @@ impl Response {
     /// input must be "percent-encoded" as per RFC3986, and space characters should also be
     /// percent-encoded.
     pub fn input(prompt: String) -> Response {
-        Response(format!("10 {}\r\n", prompt))
+        Response::header(Status::Input, &prompt)
     }
     /// As per status code [Self::input], but for use with sensitive input such as passwords.
     /// Clients should present the prompt as per status code 10, but the user's input should not
     /// be echoed to the screen to prevent it being read by "shoulder surfers".
     pub fn sensitive_input(prompt: String) -> Response {
-        Response(format!("11 {}\r\n", prompt))
+        Response::header(Status::SensitiveInput, &prompt)
     }
 
     // 2x (SUCCESS)
@@ -51,7 +113,9 @@ impl Response {
     /// The request was handled successfully and a response body will follow the response header.
     /// The <META> line is a MIME media type which applies to the response body.
     pub fn success(media_type: MediaType, contents: String) -> Response {
-        Response(format!("20 {}\r\n{}", media_type.0, contents))
+        let mut response = Response::header(Status::Success, &media_type.0);
+        response.0.push_str(&contents);
+        response
     }
 
     // 3x (REDIRECT)
@@ -64,7 +128,7 @@ impl Response {
     /// performance convenience actions like automatically updating bookmarks.
     /// There is no response body.
     pub fn redirect_temporary(url: String) -> Response {
-        Response(format!("30 {}\r\n", url))
+        Response::header(Status::RedirectTemporary, &url)
     }
 
     /// See [Self::redirect_temporary].
@@ -77,7 +141,7 @@ impl Response {
     /// won't be able to make use of the knowledge that this redirect is permanent, so they'll pay
     /// a small performance penalty by having to follow the redirect each time.
     pub fn redirect_permanent(url: String) -> Response {
-        Response(format!("31 {}\r\n", url))
+        Response::header(Status::RedirectPermanent, &url)
     }
 
     // 4x (TEMPORARY FAILURE)
@@ -88,21 +152,21 @@ impl Response {
     /// i.e. an identical request MAY succeed in the future. The contents of <META> may provide
     /// additional information on the failure, and should be displayed to human users.
     pub fn temporary_failure(reason: String) -> Response {
-        Response(format!("40 {}\r\n", reason))
+        Response::header(Status::TemporaryFailure, &reason)
     }
 
     /// See [Self::temporary_failure].
     ///
     /// The server is unavailable due to overload or maintenance. (cf HTTP 503)
     pub fn server_unavailable(reason: String) -> Response {
-        Response(format!("41 {}\r\n", reason))
+        Response::header(Status::ServerUnavailable, &reason)
     }
 
     /// See [Self::temporary_failure].
     ///
     /// A CGI process, or similar system for generating dynamic content, died unexpectedly or timed out.
     pub fn cgi_error(reason: String) -> Response {
-        Response(format!("42 {}\r\n", reason))
+        Response::header(Status::CgiError, &reason)
     }
 
     /// See [Self::temporary_failure].
@@ -110,7 +174,7 @@ impl Response {
     /// A proxy request failed because the server was unable to successfully complete a transaction
     /// with the remote host. (cf HTTP 502, 504)
     pub fn proxy_error(reason: String) -> Response {
-        Response(format!("43 {}\r\n", reason))
+        Response::header(Status::ProxyError, &reason)
     }
 
     /// See [Self::temporary_failure].
@@ -118,7 +182,7 @@ impl Response {
     /// Rate limiting is in effect. <META> is an integer number of seconds which the client must
     /// wait before another request is made to this server. (cf HTTP 429)
     pub fn slow_down(minimum_time_before_retry_allowed: Duration) -> Response {
-        Response(format!("44 {}\r\n", minimum_time_before_retry_allowed.as_secs()))
+        Response::header(Status::SlowDown, &minimum_time_before_retry_allowed.as_secs().to_string())
     }
 
     // 5x (PERMANENT FAILURE)
@@ -131,7 +195,7 @@ impl Response {
     /// users. Automatic clients such as aggregators or indexing crawlers should not repeat this
     /// request.
     pub fn permanent_failure(reason: String) -> Response {
-        Response(format!("50 {}\r\n", reason))
+        Response::header(Status::PermanentFailure, &reason)
     }
 
     /// See [Self::permanent_failure].
@@ -140,7 +204,7 @@ impl Response {
     /// (struggling to remember this important status code? Easy: you can't find things hidden
     /// at Area 51!)
     pub fn not_found(reason: String) -> Response {
-        Response(format!("51 {}\r\n", reason))
+        Response::header(Status::NotFound, &reason)
     }
 
     /// See [Self::permanent_failure].
@@ -150,7 +214,7 @@ impl Response {
     /// aggregators should stop requesting the resource and convey to their human users that the
     /// subscribed resource is gone. (cf HTTP 410)
     pub fn gone(reason: String) -> Response {
-        Response(format!("52 {}\r\n", reason))
+        Response::header(Status::Gone, &reason)
     }
 
     /// See [Self::permanent_failure].
@@ -158,7 +222,7 @@ impl Response {
     /// The request was for a resource at a domain not served by the server and the server does not
     /// accept proxy requests.
     pub fn proxy_request_refused(reason: String) -> Response {
-        Response(format!("53 {}\r\n", reason))
+        Response::header(Status::ProxyRequestRefused, &reason)
     }
 
     /// See [Self::permanent_failure].
@@ -166,7 +230,7 @@ impl Response {
     /// The server was unable to parse the client's request, presumably due to a malformed
     /// request. (cf HTTP 400)
     pub fn bad_request(reason: String) -> Response {
-        Response(format!("59 {}\r\n", reason))
+        Response::header(Status::BadRequest, &reason)
     }
 
     // 6x (CLIENT CERTIFICATE REQUIRED)
@@ -179,7 +243,7 @@ impl Response {
     /// different certificate. The contents of <META> (and/or the specific 6x code) may provide
     /// additional information on certificate requirements or the reason a certificate was rejected.
     pub fn client_certificate_required(message: String) -> Response {
-        Response(format!("60 {}\r\n", message))
+        Response::header(Status::ClientCertificateRequired, &message)
     }
 
     /// See [Self::client_certificate_required].
@@ -188,7 +252,7 @@ impl Response {
     /// resource. The problem is not with the certificate itself, which may be authorised for
     /// other resources.
     pub fn certificate_not_authorized(message: String) -> Response {
-        Response(format!("61 {}\r\n", message))
+        Response::header(Status::CertificateNotAuthorized, &message)
     }
 
     /// See [Self::client_certificate_required].
@@ -200,12 +264,27 @@ impl Response {
     /// signature, or a violation of a X509 standard requirements. The <META> should provide more
     /// information about the exact error.
     pub fn certificate_not_valid(message: String) -> Response {
-        Response(format!("62 {}\r\n", message))
+        Response::header(Status::CertificateNotValid, &message)
     }
 }
 
 impl Response {
+    fn header(status: Status, meta: &str) -> Response {
+        Response(format!("{} {}\r\n", status.code(), meta))
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         self.0.as_bytes()
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_status_codes() {
+    assert_eq!(Status::from_code(20), Some(Status::Success));
+    assert_eq!(Status::from_code(59), Some(Status::BadRequest));
+    assert_eq!(Status::from_code(57), Some(Status::PermanentFailure));
+    assert_eq!(Status::from_code(0), None);
+    assert_eq!(Status::from_code(99), None);
+    assert_eq!(Response::not_found("".to_owned()).as_bytes(), b"51 \r\n");
+    assert_eq!(Response::slow_down(Duration::from_secs(3)).as_bytes(), b"44 3\r\n");
+}
@@ -0,0 +1,204 @@
+//! Merchants that buy and sell items for gold. Shops are defined in content files, while how much
+//! is left of limited stock is kept in the database, shared by everyone.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use crate::items::{Inventory, Item, ItemKind};
+use crate::storage::User;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Shop {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// The id of the location the shop is at.
+    pub location: String,
+    /// What the shop pays for items, as a percentage of their value.
+    #[serde(default = "default_buy_percent")]
+    pub buy_percent: i32,
+    pub stock: Vec<Stock>,
+}
+
+fn default_buy_percent() -> i32 {
+    50
+}
+
+/// An item the shop sells.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stock {
+    pub item: String,
+    /// Defaults to the value of the item.
+    pub price: Option<i32>,
+    /// How many the shop has. Without a limit there is no end to them.
+    pub quantity: Option<i32>,
+    /// Minutes after which limited stock is back to full.
+    pub restock_minutes: Option<u64>,
+}
+
+/// What is left of limited stock, and when it was last full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Supply {
+    pub remaining: i32,
+    pub restocked_at: SystemTime,
+}
+
+/// Why a trade does not happen.
+#[derive(Debug, PartialEq)]
+pub enum TradeError {
+    /// Holds how many are left.
+    OutOfStock(i32),
+    NotEnoughGold,
+    /// There is no room in the inventory for all of it.
+    NoRoom,
+    NotCarried,
+    /// The shop has no interest in the item.
+    NotWanted,
+}
+
+impl Stock {
+    pub fn price(&self, item: &Item) -> i32 {
+        self.price.unwrap_or(item.value)
+    }
+
+    /// What is left of limited stock at `now`, given what was `stored`, if anything. `None` for
+    /// stock without a limit.
+    pub fn supply(&self, stored: Option<Supply>, now: SystemTime) -> Option<Supply> {
+        let quantity = self.quantity?;
+        let mut supply = stored.unwrap_or(Supply { remaining: quantity, restocked_at: now });
+        self.restock(&mut supply, now);
+        Some(supply)
+    }
+
+    /// How long until `supply` is back to full, or `None` when it never will be.
+    pub fn restocks_in(&self, supply: &Supply, now: SystemTime) -> Option<Duration> {
+        let restocked = supply.restocked_at + Duration::from_secs(self.restock_minutes? * 60);
+        Some(restocked.duration_since(now).unwrap_or_default())
+    }
+
+    /// Fills up `supply` again when the restock interval has passed since it was last full.
+    pub fn restock(&self, supply: &mut Supply, now: SystemTime) {
+        let (quantity, minutes) = match (self.quantity, self.restock_minutes) {
+            (Some(quantity), Some(minutes)) => (quantity, minutes),
+            _ => return,
+        };
+        let elapsed = now.duration_since(supply.restocked_at).unwrap_or_default();
+        if elapsed >= Duration::from_secs(minutes * 60) {
+            *supply = Supply { remaining: quantity, restocked_at: now };
+        }
+    }
+}
+
+impl Shop {
+    pub fn stock(&self, item: &str) -> Option<&Stock> {
+        self.stock.iter().find(|stock| stock.item == item)
+    }
+
+    /// What the shop pays for one of `item`, or `None` when it does not buy it. Nobody buys keys.
+    pub fn offer(&self, item: &Item) -> Option<i32> {
+        let price = item.value * self.buy_percent / 100;
+        Some(price).filter(|&price| price > 0 && item.kind != ItemKind::Key)
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.id.is_empty() || !self.id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+            problems.push(format!("shop id \"{}\" may only contain lowercase letters, digits and dashes", self.id));
+        }
+        if !(0..=100).contains(&self.buy_percent) {
+            problems.push(format!("shop {} must pay between 0 and 100 percent of the value of items", self.id));
+        }
+        let mut seen = HashSet::new();
+        for stock in &self.stock {
+            if !seen.insert(&stock.item) {
+                problems.push(format!("shop {} sells {} more than once", self.id, stock.item));
+            }
+            if stock.price.is_some_and(|price| price < 0) {
+                problems.push(format!("shop {} must not sell {} for a negative price", self.id, stock.item));
+            }
+            match (stock.quantity, stock.restock_minutes) {
+                (Some(quantity), _) if quantity < 1 => problems.push(format!("shop {} must stock at least one {}", self.id, stock.item)),
+                (None, Some(_)) => problems.push(format!("shop {} restocks {}, which has no limit", self.id, stock.item)),
+                (_, Some(0)) => problems.push(format!("shop {} must take at least a minute to restock {}", self.id, stock.item)),
+                _ => {}
+            }
+        }
+        problems
+    }
+}
+
+impl Supply {
+    /// Takes `quantity` out of what is left, changing nothing when there is not enough.
+    pub fn take(&mut self, quantity: i32) -> Result<(), TradeError> {
+        if self.remaining < quantity {
+            return Err(TradeError::OutOfStock(self.remaining));
+        }
+        self.remaining -= quantity;
+        Ok(())
+    }
+}
+
+/// Sells `quantity` of `item` to the user for `price` each. Either all of it is bought, or
+/// nothing changes. The gold checked and taken is that of `user`, which must be the user as stored
+/// and locked, such as the one [crate::storage::Storage::trade] hands out, so that concurrent
+/// purchases cannot spend the same gold twice.
+pub fn buy(items: &[Item], user: &mut User, inventory: &mut Inventory, item: &Item, price: i32, quantity: i32, capacity: i32) -> Result<(), TradeError> {
+    let cost = price.checked_mul(quantity).ok_or(TradeError::NotEnoughGold)?;
+    if cost > user.gold {
+        return Err(TradeError::NotEnoughGold);
+    }
+    let mut changed = inventory.clone();
+    if changed.add(items, item, quantity, capacity) < quantity {
+        return Err(TradeError::NoRoom);
+    }
+    *inventory = changed;
+    user.gold -= cost;
+    Ok(())
+}
+
+/// Buys `quantity` of `item` from the user for what the shop offers. Like [buy], `user` must be
+/// the user as stored and locked.
+pub fn sell(shop: &Shop, user: &mut User, inventory: &mut Inventory, item: &Item, quantity: i32) -> Result<i32, TradeError> {
+    let price = shop.offer(item).ok_or(TradeError::NotWanted)?;
+    if !inventory.remove(&item.id, quantity) {
+        return Err(TradeError::NotCarried);
+    }
+    let earned = price * quantity;
+    user.gold += earned;
+    Ok(earned)
+}
+
+#[test]
+fn test_restock() {
+    let stock = Stock { item: "herb".to_owned(), price: None, quantity: Some(3), restock_minutes: Some(10) };
+    let start = SystemTime::UNIX_EPOCH;
+    let mut supply = Supply { remaining: 1, restocked_at: start };
+    stock.restock(&mut supply, start + Duration::from_secs(9 * 60));
+    assert_eq!(supply.remaining, 1);
+    let later = start + Duration::from_secs(10 * 60);
+    stock.restock(&mut supply, later);
+    assert_eq!(supply, Supply { remaining: 3, restocked_at: later });
+
+    // Stock that is never restocked stays sold out.
+    let stock = Stock { restock_minutes: None, ..stock };
+    let mut supply = Supply { remaining: 0, restocked_at: start };
+    stock.restock(&mut supply, later);
+    assert_eq!(supply.remaining, 0);
+    assert_eq!(supply.take(1), Err(TradeError::OutOfStock(0)));
+}
+
+#[test]
+fn test_buy() {
+    let items = crate::items::test_items();
+    let herb = &items[0];
+    let mut user = User { gold: 10, ..crate::progression::test_user(1, 0) };
+    let mut inventory = Inventory::default();
+    assert_eq!(buy(&items, &mut user, &mut inventory, herb, 4, 3, 10), Err(TradeError::NotEnoughGold));
+    assert_eq!((user.gold, inventory.quantity("herb")), (10, 0));
+    assert_eq!(buy(&items, &mut user, &mut inventory, herb, 4, 2, 10), Ok(()));
+    assert_eq!((user.gold, inventory.quantity("herb")), (2, 2));
+}
@@ -1,21 +1,126 @@
 //! Communication with the database only happens through this module.
 
-use std::{env, error};
+use std::cmp::max;
+use std::{error, fmt};
+use std::time::{Duration, SystemTime};
 
-use postgres::{Client, NoTls};
+use postgres::{Client, NoTls, Row, Transaction};
+use postgres::error::SqlState;
+use postgres::types::ToSql;
 
-pub mod locations {
-    pub const BASTOW: i32 = 0;
-    pub const BASTOW_WOODLANDS: i32 = 1;
+use crate::accounts::{self, RecoverySecret};
+use crate::attributes::Attributes;
+use crate::config::StorageConfig;
+use crate::items::{Equipment, Inventory, Slot};
+use crate::progression;
+use crate::shops::Supply;
+
+/// A change to the database schema. Migrations are applied in order of their version, and each
+/// one exactly once.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "initial", sql: include_str!("../db/migrations/0001_initial.sql") },
+    Migration { version: 2, name: "location_ids", sql: include_str!("../db/migrations/0002_location_ids.sql") },
+    Migration { version: 3, name: "encounters", sql: include_str!("../db/migrations/0003_encounters.sql") },
+    Migration { version: 4, name: "knockouts", sql: include_str!("../db/migrations/0004_knockouts.sql") },
+    Migration { version: 5, name: "experience", sql: include_str!("../db/migrations/0005_experience.sql") },
+    Migration { version: 6, name: "attributes", sql: include_str!("../db/migrations/0006_attributes.sql") },
+    Migration { version: 7, name: "inventory", sql: include_str!("../db/migrations/0007_inventory.sql") },
+    Migration { version: 8, name: "equipment", sql: include_str!("../db/migrations/0008_equipment.sql") },
+    Migration { version: 9, name: "gold", sql: include_str!("../db/migrations/0009_gold.sql") },
+    Migration { version: 10, name: "shops", sql: include_str!("../db/migrations/0010_shops.sql") },
+    Migration { version: 11, name: "quests", sql: include_str!("../db/migrations/0011_quests.sql") },
+    Migration { version: 12, name: "conversations", sql: include_str!("../db/migrations/0012_conversations.sql") },
+    Migration { version: 13, name: "regeneration", sql: include_str!("../db/migrations/0013_regeneration.sql") },
+    Migration { version: 14, name: "actions", sql: include_str!("../db/migrations/0014_actions.sql") },
+    Migration { version: 15, name: "accounts", sql: include_str!("../db/migrations/0015_accounts.sql") },
+    Migration { version: 16, name: "certificates", sql: include_str!("../db/migrations/0016_certificates.sql") },
+    Migration { version: 17, name: "recovery", sql: include_str!("../db/migrations/0017_recovery.sql") },
+];
+
+const USER_COLUMNS: &str = "id, name, level, xp, strength, agility, vitality, max_health, health, health_updated_at, gold, location, last_inn, actions";
+
+/// How many times a statement is attempted when it keeps failing to serialize.
+const SERIALIZATION_ATTEMPTS: usize = 3;
+
+/// Who plays, as told by their client certificates. An account owns any number of characters, which
+/// are [User]s.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub id: i32,
+    /// Id of the character the account plays, if any.
+    pub active_user: Option<i32>,
 }
 
+/// A client certificate an account is played with.
 #[derive(Debug)]
+pub struct Certificate {
+    /// SHA-256 of the certificate.
+    pub fingerprint: Vec<u8>,
+    pub linked_at: SystemTime,
+}
+
+#[derive(Debug, Clone)]
 pub struct User {
     pub id: i32,
     pub name: String,
+    pub level: i32,
+    /// Experience, which keeps growing across levels.
+    pub xp: i32,
+    pub attributes: Attributes,
     pub max_health: i32,
     pub health: i32,
-    pub location_id: i32,
+    /// When health last went up by regeneration, or was full.
+    pub health_updated_at: SystemTime,
+    pub gold: i32,
+    /// Id of a location in the world. It may no longer exist when the world has changed.
+    pub location: String,
+    /// Id of the inn the user visited last, where they wake up when knocked out.
+    pub last_inn: Option<String>,
+    /// How many actions that change the game the user took. The next such action must carry it as
+    /// its token, and the dice for each action are derived from it, see [crate::random].
+    pub actions: i64,
+}
+
+/// Something notable that happened to a user.
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub happened_at: SystemTime,
+    /// What kind of thing happened, such as `knocked-out`.
+    pub kind: String,
+    pub description: String,
+}
+
+/// A character that is still being created.
+#[derive(Debug)]
+pub struct Draft {
+    pub name: String,
+    pub attributes: Attributes,
+}
+
+/// A fight between a user and a monster that is still going on.
+#[derive(Debug)]
+pub struct Encounter {
+    /// Id of the monster being fought.
+    pub monster: String,
+    pub monster_health: i32,
+    /// How many rounds have been fought so far.
+    pub round: i32,
+}
+
+/// A quest a user has taken on.
+#[derive(Debug)]
+pub struct QuestProgress {
+    /// Id of the quest.
+    pub quest: String,
+    /// A count for each objective of the quest.
+    pub progress: Vec<i32>,
+    pub completed: bool,
 }
 
 pub struct Storage {
@@ -24,26 +129,134 @@ pub struct Storage {
 
 #[derive(Debug)]
 pub enum Error {
+    /// The database could not be reached, or the connection to it was lost.
+    Connection(Box<dyn error::Error + Sync + Send>),
+    /// A `unique` constraint was violated. Holds the name of the constraint.
+    UniqueViolation(String),
+    /// A `check` constraint was violated. Holds the name of the constraint.
+    CheckViolation(String),
+    /// The statement conflicted with a concurrent transaction, even after being retried.
+    SerializationFailure,
     Db(Box<dyn error::Error + Sync + Send>),
     MissingPrimaryKeyRow,
     NotFound,
 }
 
 impl From<postgres::Error> for Error {
-    fn from(e: postgres::Error) -> Self { Error::Db(Box::new(e)) }
+    fn from(e: postgres::Error) -> Self {
+        if let Some(db_error) = e.as_db_error() {
+            let constraint = db_error.constraint().unwrap_or_default().to_owned();
+            let code = db_error.code();
+            if code == &SqlState::UNIQUE_VIOLATION {
+                return Error::UniqueViolation(constraint);
+            }
+            if code == &SqlState::CHECK_VIOLATION {
+                return Error::CheckViolation(constraint);
+            }
+            if code == &SqlState::T_R_SERIALIZATION_FAILURE || code == &SqlState::T_R_DEADLOCK_DETECTED {
+                return Error::SerializationFailure;
+            }
+            // Class 08 is "Connection Exception", 57P01 to 57P03 are the server shutting down.
+            if code.code().starts_with("08") || code.code().starts_with("57P0") {
+                return Error::Connection(Box::new(e));
+            }
+        }
+        if e.is_closed() {
+            return Error::Connection(Box::new(e));
+        }
+        Error::Db(Box::new(e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connection(e) => write!(f, "database connection failed: {}", e),
+            Error::UniqueViolation(constraint) => write!(f, "unique constraint {} violated", constraint),
+            Error::CheckViolation(constraint) => write!(f, "check constraint {} violated", constraint),
+            Error::SerializationFailure => write!(f, "could not serialize access due to concurrent update"),
+            Error::Db(e) => write!(f, "database error: {}", e),
+            Error::MissingPrimaryKeyRow => write!(f, "insert did not return a primary key"),
+            Error::NotFound => write!(f, "not found"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Runs `f` again when it fails to serialize with a concurrent transaction, which postgres
+/// explicitly allows to be retried.
+fn retry<T>(mut f: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(Error::SerializationFailure) if attempt < SERIALIZATION_ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
 }
 
 impl Storage {
     // TODO: db pooling
-    pub fn new() -> Result<Storage, Error> {
-        let host = env::var("POSTGRES_HOST").unwrap_or("localhost".to_owned());
-        let user = env::var("POSTGRES_USER").unwrap_or("postgres".to_owned());
-        let password = env::var("POSTGRES_PASSWORD").unwrap_or("postgres".to_owned());
-        Ok(Storage { client: Client::connect(format!("host={} user={} password={}", host, user, password).as_str(), NoTls)? })
+    pub fn new(config: &StorageConfig) -> Result<Storage, Error> {
+        let StorageConfig { host, user, password, .. } = config;
+        let client = Client::connect(format!("host={} user={} password={}", host, user, password).as_str(), NoTls)
+            .map_err(|e| Error::Connection(Box::new(e)))?;
+        Ok(Storage { client })
+    }
+
+    /// Executes a statement, retrying it when it fails to serialize with a concurrent transaction.
+    fn execute(&mut self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, Error> {
+        retry(|| Ok(self.client.execute(statement, params)?))
+    }
+
+    /// Queries rows, retrying when the query fails to serialize with a concurrent transaction.
+    fn query(&mut self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, Error> {
+        retry(|| Ok(self.client.query(statement, params)?))
+    }
+
+    /// Runs `f` in a transaction, which is committed when `f` succeeds. The whole transaction is
+    /// retried when it fails to serialize with a concurrent one.
+    fn transaction<T>(&mut self, mut f: impl FnMut(&mut Transaction) -> Result<T, Error>) -> Result<T, Error> {
+        retry(|| {
+            let mut transaction = self.client.transaction()?;
+            let result = f(&mut transaction)?;
+            transaction.commit()?;
+            Ok(result)
+        })
+    }
+
+    /// Applies the migrations that have not been applied yet, returning them.
+    pub fn migrate(&mut self) -> Result<Vec<&'static Migration>, Error> {
+        self.client.batch_execute(
+            "create table if not exists schema_migrations (\
+                version int primary key, \
+                name text not null, \
+                applied_at timestamptz not null default now()\
+            )",
+        )?;
+        let mut transaction = self.client.transaction()?;
+        // Keeps two servers starting at the same time from applying the same migration twice.
+        transaction.batch_execute("lock table schema_migrations in exclusive mode")?;
+        let applied = transaction.query("select version from schema_migrations", &[])?
+            .iter()
+            .map(|row| row.get::<_, i32>(0))
+            .collect::<Vec<_>>();
+        let mut pending = vec![];
+        for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+            transaction.batch_execute(migration.sql)?;
+            transaction.execute(
+                "insert into schema_migrations (version, name) values ($1, $2)",
+                &[&migration.version, &migration.name],
+            )?;
+            pending.push(migration);
+        }
+        transaction.commit()?;
+        Ok(pending)
     }
 
     pub fn count_users(&mut self) -> Result<i64, Error> {
-        match self.client.query("select count(*) from users", &[])?.first() {
+        match self.query("select count(*) from users", &[])?.first() {
             Some(row) => {
                 let count = row.get(0);
                 Ok(count)
@@ -52,57 +265,675 @@ impl Storage {
         }
     }
 
-    pub fn create_user(&mut self, fingerprint: &[u8], name: String) -> Result<User, Error> {
-        let max_health = 10;
-        let health = 10;
-        let location_id = locations::BASTOW;
-        match self.client.query(
-            "insert into users (fingerprint, name, max_health, health, location_id) values ($1, $2, $3, $4, $5) RETURNING id",
-            &[&fingerprint, &name, &max_health, &health, &location_id],
-        )?.first() {
-            Some(row) => {
-                let id = row.get(0);
-                Ok(User { id, name, max_health, health, location_id })
+    /// Loads the account with the certificate `fingerprint`, creating it when there is none yet.
+    pub fn get_account(&mut self, fingerprint: &[u8]) -> Result<Account, Error> {
+        let statement = "select accounts.id, accounts.active_user_id from certificates \
+            join accounts on accounts.id = certificates.account_id where certificates.fingerprint = $1";
+        if let Some(row) = self.query(statement, &[&fingerprint])?.first() {
+            return Ok(Account { id: row.get(0), active_user: row.get(1) });
+        }
+        let created = self.transaction(|transaction| {
+            let row = transaction.query("insert into accounts default values returning id", &[])?
+                .into_iter().next().ok_or(Error::MissingPrimaryKeyRow)?;
+            let id: i32 = row.get(0);
+            // Another request with the same certificate created an account first, so this one is
+            // rolled back.
+            match transaction.execute(
+                "insert into certificates (fingerprint, account_id) values ($1, $2) on conflict do nothing",
+                &[&fingerprint, &id],
+            )? {
+                0 => Err(Error::NotFound),
+                _ => Ok(()),
+            }
+        });
+        match created {
+            Ok(()) | Err(Error::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        match self.query(statement, &[&fingerprint])?.first() {
+            Some(row) => Ok(Account { id: row.get(0), active_user: row.get(1) }),
+            None => Err(Error::MissingPrimaryKeyRow),
+        }
+    }
+
+    /// The certificates the account is played with, in the order they were linked.
+    pub fn list_certificates(&mut self, account: &Account) -> Result<Vec<Certificate>, Error> {
+        let rows = self.query(
+            "select fingerprint, linked_at from certificates where account_id = $1 order by linked_at, fingerprint",
+            &[&account.id],
+        )?;
+        Ok(rows.iter().map(|row| Certificate { fingerprint: row.get(0), linked_at: row.get(1) }).collect())
+    }
+
+    /// Makes `code` the pairing code of the account for `lifetime`, replacing the one it had.
+    pub fn set_pairing_code(&mut self, account: &Account, code: &str, lifetime: Duration) -> Result<(), Error> {
+        let seconds = lifetime.as_secs_f64();
+        self.execute(
+            "insert into pairing_codes (account_id, code, expires_at) values ($1, $2, now() + $3 * interval '1 second') \
+            on conflict (account_id) do update set code = $2, expires_at = now() + $3 * interval '1 second'",
+            &[&account.id, &code, &seconds],
+        )?;
+        Ok(())
+    }
+
+    /// Uses up the pairing `code` to link the certificate `fingerprint` to the account that made
+    /// the code, returning that account. When no other certificate is left to play the account the
+    /// certificate had, its characters move along and it is deleted. Fails with `NotFound` when
+    /// there is no such code, or it has expired.
+    pub fn link_certificate(&mut self, fingerprint: &[u8], code: &str) -> Result<Account, Error> {
+        self.transaction(|transaction| {
+            let row = transaction.query(
+                "delete from pairing_codes where code = $1 and expires_at > now() returning account_id",
+                &[&code],
+            )?.into_iter().next().ok_or(Error::NotFound)?;
+            attach_certificate(transaction, fingerprint, row.get(0))
+        })
+    }
+
+    pub fn has_recovery_secret(&mut self, account: &Account) -> Result<bool, Error> {
+        let rows = self.query("select from accounts where id = $1 and recovery_hash is not null", &[&account.id])?;
+        Ok(!rows.is_empty())
+    }
+
+    /// Sets the recovery secret of the account, or removes it with `None`.
+    pub fn set_recovery_secret(&mut self, account: &Account, secret: Option<&RecoverySecret>) -> Result<(), Error> {
+        self.execute(
+            "update accounts set recovery_salt = $1, recovery_hash = $2 where id = $3",
+            &[&secret.map(|secret| &secret.salt), &secret.map(|secret| &secret.hash), &account.id],
+        )?;
+        Ok(())
+    }
+
+    /// Records an attempt to recover the account with `id`, unless `limit` attempts were made in
+    /// the last `window` already, in which case it tells how long until the next one may be made.
+    /// Returns the recovery secret to check the attempt against, or `None` when there is no such
+    /// account, or it has no secret.
+    pub fn attempt_recovery(&mut self, id: i32, limit: usize, window: Duration) -> Result<Result<Option<RecoverySecret>, Duration>, Error> {
+        let seconds = window.as_secs_f64();
+        self.transaction(|transaction| {
+            // Locking the account keeps concurrent attempts from slipping past the limit.
+            let secret = transaction.query("select recovery_salt, recovery_hash from accounts where id = $1 for update", &[&id])?
+                .first()
+                .and_then(|row| Some(RecoverySecret { salt: row.get::<_, Option<_>>(0)?, hash: row.get::<_, Option<_>>(1)? }));
+            let secret = match secret {
+                Some(secret) => secret,
+                None => return Ok(Ok(None)),
+            };
+            let attempts = transaction.query(
+                "select attempted_at from recovery_attempts where account_id = $1 and attempted_at > now() - $2 * interval '1 second' \
+                order by attempted_at",
+                &[&id, &seconds],
+            )?.iter().map(|row| row.get(0)).collect::<Vec<SystemTime>>();
+            if let Some(wait) = accounts::retry_in(&attempts, limit, window, SystemTime::now()) {
+                return Ok(Err(wait));
             }
-            None => Err(Error::MissingPrimaryKeyRow)
+            transaction.execute("insert into recovery_attempts (account_id) values ($1)", &[&id])?;
+            Ok(Ok(Some(secret)))
+        })
+    }
+
+    /// Links the certificate `fingerprint` to the account with `id`, whose recovery secret was
+    /// entered, the way [Self::link_certificate] does.
+    pub fn recover_account(&mut self, fingerprint: &[u8], id: i32) -> Result<Account, Error> {
+        self.transaction(|transaction| {
+            transaction.execute("delete from recovery_attempts where account_id = $1", &[&id])?;
+            attach_certificate(transaction, fingerprint, id)
+        })
+    }
+
+    /// Unlinks the certificate `fingerprint` from the account, so that it can no longer play it.
+    pub fn revoke_certificate(&mut self, account: &Account, fingerprint: &[u8]) -> Result<(), Error> {
+        match self.execute("delete from certificates where account_id = $1 and fingerprint = $2", &[&account.id, &fingerprint])? {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
         }
     }
 
-    pub fn get_user(&mut self, fingerprint: &[u8]) -> Result<User, Error> {
-        match self.client.query("select id, name, max_health, health, location_id from users where fingerprint = $1", &[&fingerprint])?.first() {
+    /// Deletes the account, and with it its certificates, characters and everything else about it.
+    pub fn delete_account(&mut self, account: &Account) -> Result<(), Error> {
+        match self.execute("delete from accounts where id = $1", &[&account.id])? {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates a user from the `draft` of the account, which is deleted, and makes it the one the
+    /// account plays. Fails with `NotFound` when there is no draft, such as when another request
+    /// created the character first.
+    pub fn create_user(&mut self, account: &Account, draft: Draft, max_health: i32, gold: i32, location: String) -> Result<User, Error> {
+        let health = max_health;
+        let Draft { name, attributes } = draft;
+        let id = self.transaction(|transaction| {
+            if transaction.execute("delete from character_drafts where account_id = $1", &[&account.id])? == 0 {
+                return Err(Error::NotFound);
+            }
+            let row = transaction.query(
+                "insert into users (account_id, name, strength, agility, vitality, max_health, health, gold, location) \
+                values ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+                &[&account.id, &name, &attributes.strength, &attributes.agility, &attributes.vitality, &max_health, &health, &gold, &location],
+            )?.into_iter().next().ok_or(Error::MissingPrimaryKeyRow)?;
+            let id: i32 = row.get(0);
+            transaction.execute("update accounts set active_user_id = $1 where id = $2", &[&id, &account.id])?;
+            Ok(id)
+        })?;
+        Ok(User { id, name, level: 1, xp: 0, attributes, max_health, health, health_updated_at: SystemTime::now(), gold, location, last_inn: None, actions: 0 })
+    }
+
+    pub fn get_draft(&mut self, account: &Account) -> Result<Option<Draft>, Error> {
+        let rows = self.query(
+            "select name, strength, agility, vitality from character_drafts where account_id = $1",
+            &[&account.id],
+        )?;
+        Ok(rows.first().map(|row| Draft {
+            name: row.get(0),
+            attributes: Attributes { strength: row.get(1), agility: row.get(2), vitality: row.get(3) },
+        }))
+    }
+
+    pub fn save_draft(&mut self, account: &Account, draft: &Draft) -> Result<(), Error> {
+        let attributes = &draft.attributes;
+        self.execute(
+            "insert into character_drafts (account_id, name, strength, agility, vitality) values ($1, $2, $3, $4, $5) \
+            on conflict (account_id) do update set name = $2, strength = $3, agility = $4, vitality = $5",
+            &[&account.id, &draft.name, &attributes.strength, &attributes.agility, &attributes.vitality],
+        )?;
+        Ok(())
+    }
+
+    /// Loads the user the account with the certificate `fingerprint` plays. With `regeneration`,
+    /// the health they regenerated since they were last updated is added, to be stored along with
+    /// the next change.
+    pub fn get_user(&mut self, fingerprint: &[u8], regeneration: Option<Duration>) -> Result<User, Error> {
+        let statement = format!("select {} from users where id = (select active_user_id from accounts \
+            join certificates on certificates.account_id = accounts.id where certificates.fingerprint = $1)", USER_COLUMNS);
+        match self.query(&statement, &[&fingerprint])?.first() {
             Some(row) => {
-                let id = row.get(0);
-                let name = row.get(1);
-                let max_health = row.get(2);
-                let health = row.get(3);
-                let location_id = row.get(4);
-                Ok(User { id, name, max_health, health, location_id })
+                let mut user = user_from_row(row);
+                if let Some(interval) = regeneration {
+                    progression::regenerate(&mut user, interval, SystemTime::now());
+                }
+                Ok(user)
             }
             None => Err(Error::NotFound)
         }
     }
 
+    /// The characters of the account, oldest first.
+    pub fn list_characters(&mut self, account: &Account) -> Result<Vec<User>, Error> {
+        let statement = format!("select {} from users where account_id = $1 order by id", USER_COLUMNS);
+        Ok(self.query(&statement, &[&account.id])?.iter().map(user_from_row).collect())
+    }
+
+    /// Makes the character with `id`, which must belong to the account, the one it plays.
+    pub fn select_character(&mut self, account: &Account, id: i32) -> Result<(), Error> {
+        match self.execute(
+            "update accounts set active_user_id = $1 where id = $2 and exists (select from users where id = $1 and account_id = $2)",
+            &[&id, &account.id],
+        )? {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes the character with `id`, which must belong to the account, with everything it has.
+    pub fn delete_character(&mut self, account: &Account, id: i32) -> Result<(), Error> {
+        match self.execute("delete from users where id = $1 and account_id = $2", &[&id, &account.id])? {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_user_by_id(&mut self, id: i32) -> Result<User, Error> {
+        let statement = format!("select {} from users where id = $1", USER_COLUMNS);
+        match self.query(&statement, &[&id])?.first() {
+            Some(row) => Ok(user_from_row(row)),
+            None => Err(Error::NotFound)
+        }
+    }
+
+    pub fn list_users(&mut self) -> Result<Vec<User>, Error> {
+        let statement = format!("select {} from users order by id", USER_COLUMNS);
+        Ok(self.query(&statement, &[])?.iter().map(user_from_row).collect())
+    }
+
+    /// Counts the users at each location, ordered by location.
+    pub fn count_users_by_location(&mut self) -> Result<Vec<(String, i64)>, Error> {
+        let rows = self.query("select location, count(*) from users group by location order by location", &[])?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    pub fn delete_user(&mut self, id: i32) -> Result<(), Error> {
+        match self.execute("delete from users where id = $1", &[&id])? {
+            0 => Err(Error::NotFound),
+            _ => Ok(())
+        }
+    }
+
+    /// Puts the user back into the state of a newly created character, keeping only the name.
+    pub fn reset_user(&mut self, user: User, max_health: i32, gold: i32, location: String) -> Result<User, Error> {
+        let health = max_health;
+        self.execute(
+            "update users set level = 1, xp = 0, max_health = $1, health = $2, health_updated_at = now(), gold = $3, location = $4, \
+            last_inn = null where id = $5",
+            &[&max_health, &health, &gold, &location, &user.id],
+        )?;
+        Ok(User { level: 1, xp: 0, max_health, health, health_updated_at: SystemTime::now(), gold, location, last_inn: None, ..user })
+    }
+
     pub fn update_name(&mut self, user: User, name: String) -> Result<User, Error> {
-        self.client.execute(
+        self.execute(
             "update users set name = $1 where id = $2",
             &[&name, &user.id],
         )?;
         Ok(User { name, ..user })
     }
 
-    pub fn update_health(&mut self, user: User, health: i32) -> Result<User, Error> {
-        self.client.execute(
-            "update users set health = $1 where id = $2",
-            &[&health, &user.id],
+    /// Uses up the action token `token`, which is only valid while it matches the number of actions
+    /// the user took. Fails with `NotFound` when it does not, such as when it was used already.
+    /// Returns the user as stored after the previous action, which `user` may predate, with
+    /// `regeneration` applied like [Self::get_user] does.
+    pub fn use_action_token(&mut self, user: User, token: i64, regeneration: Option<Duration>) -> Result<User, Error> {
+        let statement = format!("update users set actions = actions + 1 where id = $1 and actions = $2 returning {}", USER_COLUMNS);
+        let mut user = match self.query(&statement, &[&user.id, &token])?.first() {
+            Some(row) => user_from_row(row),
+            None => return Err(Error::NotFound),
+        };
+        if let Some(interval) = regeneration {
+            progression::regenerate(&mut user, interval, SystemTime::now());
+        }
+        Ok(user)
+    }
+
+    /// Restores the user's health at the inn they are at, which becomes their last inn, for
+    /// `price` gold. Fails with a violation of `users_gold_check` when they cannot pay.
+    pub fn rest(&mut self, user: User, health: i32, price: i32) -> Result<User, Error> {
+        let rows = self.query(
+            "update users set health = $1, health_updated_at = now(), gold = gold - $2, last_inn = location where id = $3 returning gold",
+            &[&health, &price, &user.id],
+        )?;
+        let gold = rows.first().ok_or(Error::NotFound)?.get(0);
+        let last_inn = Some(user.location.clone());
+        Ok(User { health, health_updated_at: SystemTime::now(), gold, last_inn, ..user })
+    }
+
+    /// Moves the user to `location`, taking the `cost` of the journey from their health as stored,
+    /// with `regeneration` applied like [Self::get_user] does. Pass `inn` when the location has an
+    /// inn, to make it the user's last inn.
+    pub fn travel(&mut self, user: User, regeneration: Option<Duration>, location: String, cost: i32, inn: bool) -> Result<User, Error> {
+        self.transaction(|transaction| {
+            let user = lock_user(transaction, user.id, regeneration)?;
+            let health = max(0, user.health - cost);
+            transaction.execute(
+                "update users set location = $1, health = $2, health_updated_at = $3, last_inn = case when $4 then $1 else last_inn end \
+                where id = $5",
+                &[&location, &health, &user.health_updated_at, &inn, &user.id],
+            )?;
+            let last_inn = if inn { Some(location.clone()) } else { user.last_inn.clone() };
+            Ok(User { location: location.clone(), health, last_inn, ..user })
+        })
+    }
+
+    /// Carries a knocked out user to `inn`, ending any fight and recording `description` in their
+    /// history. `change` sets the health they wake up with and takes what they lose, from the user
+    /// as stored and locked. Returns the changed user and what `change` returned.
+    pub fn knock_out<T>(
+        &mut self,
+        user: User,
+        inn: String,
+        description: &str,
+        mut change: impl FnMut(&mut User) -> T,
+    ) -> Result<(User, T), Error> {
+        self.transaction(|transaction| {
+            let mut user = lock_user(transaction, user.id, None)?;
+            let result = change(&mut user);
+            transaction.execute(
+                "update users set location = $1, health = $2, health_updated_at = now(), xp = $3, gold = $4, last_inn = $1 where id = $5",
+                &[&inn, &user.health, &user.xp, &user.gold, &user.id],
+            )?;
+            transaction.execute("delete from encounters where user_id = $1", &[&user.id])?;
+            transaction.execute(
+                "insert into history (user_id, kind, description) values ($1, 'knocked-out', $2)",
+                &[&user.id, &description],
+            )?;
+            let user = User { location: inn.clone(), health_updated_at: SystemTime::now(), last_inn: Some(inn.clone()), ..user };
+            Ok((user, result))
+        })
+    }
+
+    /// Lists the user's most recent history, newest first.
+    pub fn get_history(&mut self, user: &User, limit: i64) -> Result<Vec<HistoryEntry>, Error> {
+        let rows = self.query(
+            "select happened_at, kind, description from history where user_id = $1 order by id desc limit $2",
+            &[&user.id, &limit],
+        )?;
+        Ok(rows.iter().map(|row| HistoryEntry { happened_at: row.get(0), kind: row.get(1), description: row.get(2) }).collect())
+    }
+
+    pub fn get_inventory(&mut self, user: &User) -> Result<Inventory, Error> {
+        let rows = self.query("select item, quantity from inventory where user_id = $1 order by item", &[&user.id])?;
+        Ok(Inventory::new(rows.iter().map(|row| (row.get(0), row.get(1))).collect()))
+    }
+
+    pub fn get_equipment(&mut self, user: &User) -> Result<Equipment, Error> {
+        let rows = self.query("select slot, item from equipment where user_id = $1", &[&user.id])?;
+        Ok(equipment_from_rows(&rows))
+    }
+
+    /// Lets `change` change the user's inventory, equipment, experience, health and gold together,
+    /// with the user locked so that concurrent changes do not get lost. `change` is given the user as
+    /// stored, not `user`, which may be out of date, with the health they regenerated added, like
+    /// [Self::get_user] does. Returns the changed user and what `change` returned.
+    pub fn update_inventory<T>(
+        &mut self,
+        user: User,
+        regeneration: Option<Duration>,
+        mut change: impl FnMut(&mut User, &mut Inventory, &mut Equipment) -> T,
+    ) -> Result<(User, T), Error> {
+        self.transaction(|transaction| change_inventory(transaction, user.id, regeneration, &mut change))
+    }
+
+    /// What is left of the limited stock of a shop, by item. Items that have not been sold yet are
+    /// missing.
+    pub fn get_supplies(&mut self, shop: &str) -> Result<Vec<(String, Supply)>, Error> {
+        let rows = self.query("select item, remaining, restocked_at from shop_stock where shop = $1", &[&shop])?;
+        Ok(rows.iter().map(|row| (row.get(0), Supply { remaining: row.get(1), restocked_at: row.get(2) })).collect())
+    }
+
+    /// Like [Storage::update_inventory], but also lets `change` take from what the shop has left
+    /// of `item`, which starts out at `quantity`.
+    pub fn trade<T>(
+        &mut self,
+        user: User,
+        regeneration: Option<Duration>,
+        shop: &str,
+        item: &str,
+        quantity: i32,
+        mut change: impl FnMut(&mut User, &mut Inventory, &mut Supply) -> T,
+    ) -> Result<(User, T), Error> {
+        self.transaction(|transaction| {
+            transaction.execute(
+                "insert into shop_stock (shop, item, remaining) values ($1, $2, $3) on conflict do nothing",
+                &[&shop, &item, &quantity],
+            )?;
+            let row = transaction.query_one(
+                "select remaining, restocked_at from shop_stock where shop = $1 and item = $2 for update",
+                &[&shop, &item],
+            )?;
+            let mut supply = Supply { remaining: row.get(0), restocked_at: row.get(1) };
+            let result = change_inventory(transaction, user.id, regeneration, &mut |user, inventory, _| change(user, inventory, &mut supply))?;
+            transaction.execute(
+                "update shop_stock set remaining = $1, restocked_at = $2 where shop = $3 and item = $4",
+                &[&supply.remaining, &supply.restocked_at, &shop, &item],
+            )?;
+            Ok(result)
+        })
+    }
+
+    /// Lists the quests the user has taken on, completed or not, in the order they were taken on.
+    pub fn get_quests(&mut self, user: &User) -> Result<Vec<QuestProgress>, Error> {
+        let rows = self.query(
+            "select quest, progress, completed_at is not null from quests where user_id = $1 order by started_at",
+            &[&user.id],
+        )?;
+        Ok(rows.iter().map(|row| QuestProgress { quest: row.get(0), progress: row.get(1), completed: row.get(2) }).collect())
+    }
+
+    /// Takes on a quest, unless the user already has.
+    pub fn start_quest(&mut self, user: &User, quest: &str, progress: &[i32]) -> Result<(), Error> {
+        self.execute(
+            "insert into quests (user_id, quest, progress) values ($1, $2, $3) on conflict do nothing",
+            &[&user.id, &quest, &progress],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_quest_progress(&mut self, user: &User, quest: &str, progress: &[i32]) -> Result<(), Error> {
+        self.execute(
+            "update quests set progress = $1 where user_id = $2 and quest = $3 and completed_at is null",
+            &[&progress, &user.id, &quest],
+        )?;
+        Ok(())
+    }
+
+    /// Lets `change` hand out the rewards of a quest, like [Storage::update_inventory]. When it
+    /// succeeds, the quest is completed and `description` recorded in the user's history. Fails with
+    /// [Error::NotFound] when the user has not taken on the quest, or already completed it.
+    pub fn complete_quest<T, E>(
+        &mut self,
+        user: User,
+        regeneration: Option<Duration>,
+        quest: &str,
+        description: &str,
+        mut change: impl FnMut(&mut User, &mut Inventory, &mut Equipment) -> Result<T, E>,
+    ) -> Result<(User, Result<T, E>), Error> {
+        self.transaction(|transaction| {
+            let rows = transaction.query(
+                "select 1 from quests where user_id = $1 and quest = $2 and completed_at is null for update",
+                &[&user.id, &quest],
+            )?;
+            if rows.is_empty() {
+                return Err(Error::NotFound);
+            }
+            let mut level = user.level;
+            let (changed, result) = change_inventory(transaction, user.id, regeneration, &mut |user, inventory, equipment| {
+                level = user.level;
+                change(user, inventory, equipment)
+            })?;
+            if result.is_ok() {
+                transaction.execute(
+                    "update quests set completed_at = now() where user_id = $1 and quest = $2",
+                    &[&user.id, &quest],
+                )?;
+                transaction.execute(
+                    "insert into history (user_id, kind, description) values ($1, 'quest', $2)",
+                    &[&user.id, &description],
+                )?;
+                if changed.level > level {
+                    transaction.execute(
+                        "insert into history (user_id, kind, description) values ($1, 'level-up', $2)",
+                        &[&user.id, &format!("Reached level {}.", changed.level)],
+                    )?;
+                }
+            }
+            Ok((changed, result))
+        })
+    }
+
+    /// The ids of the non-player character the user is talking to, and of the node the
+    /// conversation is at.
+    pub fn get_conversation(&mut self, user: &User) -> Result<Option<(String, String)>, Error> {
+        let rows = self.query("select npc, node from conversations where user_id = $1", &[&user.id])?;
+        Ok(rows.first().map(|row| (row.get(0), row.get(1))))
+    }
+
+    pub fn set_conversation(&mut self, user: &User, npc: &str, node: &str) -> Result<(), Error> {
+        self.execute(
+            "insert into conversations (user_id, npc, node) values ($1, $2, $3) \
+            on conflict (user_id) do update set npc = $2, node = $3",
+            &[&user.id, &npc, &node],
         )?;
-        Ok(User { health, ..user })
+        Ok(())
+    }
+
+    pub fn end_conversation(&mut self, user: &User) -> Result<(), Error> {
+        self.execute("delete from conversations where user_id = $1", &[&user.id])?;
+        Ok(())
+    }
+
+    pub fn get_encounter(&mut self, user: &User) -> Result<Option<Encounter>, Error> {
+        let rows = self.query("select monster, monster_health, round from encounters where user_id = $1", &[&user.id])?;
+        Ok(rows.first().map(|row| Encounter { monster: row.get(0), monster_health: row.get(1), round: row.get(2) }))
+    }
+
+    pub fn start_encounter(&mut self, user: &User, monster: String, monster_health: i32) -> Result<Encounter, Error> {
+        self.execute(
+            "insert into encounters (user_id, monster, monster_health) values ($1, $2, $3)",
+            &[&user.id, &monster, &monster_health],
+        )?;
+        Ok(Encounter { monster, monster_health, round: 0 })
+    }
+
+    /// Stores the outcome of a round: lets `change` apply what the round did to the user's health
+    /// and progress, with the user locked like [Self::update_inventory] does, and stores the
+    /// encounter if the fight goes on, or no encounter if it is over. Reaching a new level is
+    /// recorded in the user's history. Returns the changed user and what `change` returned.
+    pub fn finish_round<T>(
+        &mut self,
+        user: User,
+        regeneration: Option<Duration>,
+        encounter: Option<&Encounter>,
+        mut change: impl FnMut(&mut User) -> T,
+    ) -> Result<(User, T), Error> {
+        self.transaction(|transaction| {
+            let mut user = lock_user(transaction, user.id, regeneration)?;
+            let level = user.level;
+            let result = change(&mut user);
+            transaction.execute(
+                "update users set level = $1, xp = $2, max_health = $3, health = $4, health_updated_at = $5, gold = $6 where id = $7",
+                &[&user.level, &user.xp, &user.max_health, &user.health, &user.health_updated_at, &user.gold, &user.id],
+            )?;
+            match encounter {
+                Some(encounter) => transaction.execute(
+                    "update encounters set monster_health = $1, round = $2 where user_id = $3",
+                    &[&encounter.monster_health, &encounter.round, &user.id],
+                )?,
+                None => transaction.execute("delete from encounters where user_id = $1", &[&user.id])?,
+            };
+            if user.level > level {
+                transaction.execute(
+                    "insert into history (user_id, kind, description) values ($1, 'level-up', $2)",
+                    &[&user.id, &format!("Reached level {}.", user.level)],
+                )?;
+            }
+            Ok((user, result))
+        })
+    }
+}
+
+/// Reads the user with `id` and locks them until the transaction ends, with `regeneration`
+/// applied like [Storage::get_user] does.
+fn lock_user(transaction: &mut Transaction, id: i32, regeneration: Option<Duration>) -> Result<User, Error> {
+    let statement = format!("select {} from users where id = $1 for update", USER_COLUMNS);
+    let mut user = match transaction.query(statement.as_str(), &[&id])?.first() {
+        Some(row) => user_from_row(row),
+        None => return Err(Error::NotFound),
+    };
+    if let Some(interval) = regeneration {
+        progression::regenerate(&mut user, interval, SystemTime::now());
     }
+    Ok(user)
+}
 
-    pub fn update_location_id(&mut self, user: User, location_id: i32) -> Result<User, Error> {
-        self.client.execute(
-            "update users set location_id = $1 where id = $2",
-            &[&location_id, &user.id],
+/// Reads the user with `id`, locked, and their inventory and equipment, lets `change` change them,
+/// and writes them back.
+fn change_inventory<T>(
+    transaction: &mut Transaction,
+    id: i32,
+    regeneration: Option<Duration>,
+    change: &mut impl FnMut(&mut User, &mut Inventory, &mut Equipment) -> T,
+) -> Result<(User, T), Error> {
+    let mut user = lock_user(transaction, id, regeneration)?;
+    let rows = transaction.query("select item, quantity from inventory where user_id = $1 order by item", &[&user.id])?;
+    let mut inventory = Inventory::new(rows.iter().map(|row| (row.get(0), row.get(1))).collect());
+    let rows = transaction.query("select slot, item from equipment where user_id = $1", &[&user.id])?;
+    let mut equipment = equipment_from_rows(&rows);
+    let result = change(&mut user, &mut inventory, &mut equipment);
+
+    transaction.execute(
+        "update users set level = $1, xp = $2, health = $3, health_updated_at = $4, max_health = $5, gold = $6 where id = $7",
+        &[&user.level, &user.xp, &user.health, &user.health_updated_at, &user.max_health, &user.gold, &user.id],
+    )?;
+    transaction.execute("delete from inventory where user_id = $1", &[&user.id])?;
+    for (item, quantity) in inventory.entries() {
+        transaction.execute(
+            "insert into inventory (user_id, item, quantity) values ($1, $2, $3)",
+            &[&user.id, item, quantity],
+        )?;
+    }
+    transaction.execute("delete from equipment where user_id = $1", &[&user.id])?;
+    for (slot, item) in equipment.slots() {
+        transaction.execute(
+            "insert into equipment (user_id, slot, item) values ($1, $2, $3)",
+            &[&user.id, &slot.segment(), item],
         )?;
-        Ok(User { location_id, ..user })
     }
-}
\ No newline at end of file
+    Ok((user, result))
+}
+
+/// Links the certificate `fingerprint` to the account with `id`, returning that account. When no
+/// other certificate is left to play the account the certificate had, its characters move along
+/// and it is deleted.
+fn attach_certificate(transaction: &mut Transaction, fingerprint: &[u8], id: i32) -> Result<Account, Error> {
+    let previous = transaction.query("select account_id from certificates where fingerprint = $1", &[&fingerprint])?
+        .first().map(|row| row.get::<_, i32>(0));
+    match previous {
+        Some(previous) if previous == id => {}
+        Some(previous) => {
+            transaction.execute(
+                "update certificates set account_id = $1, linked_at = now() where fingerprint = $2",
+                &[&id, &fingerprint],
+            )?;
+            let left = transaction.query("select from certificates where account_id = $1", &[&previous])?;
+            if left.is_empty() {
+                transaction.execute("update users set account_id = $1 where account_id = $2", &[&id, &previous])?;
+                transaction.execute("delete from accounts where id = $1", &[&previous])?;
+            }
+        }
+        None => {
+            transaction.execute("insert into certificates (fingerprint, account_id) values ($1, $2)", &[&fingerprint, &id])?;
+        }
+    }
+    let row = transaction.query("select id, active_user_id from accounts where id = $1", &[&id])?
+        .into_iter().next().ok_or(Error::NotFound)?;
+    Ok(Account { id: row.get(0), active_user: row.get(1) })
+}
+
+/// Slots that no longer exist are left out.
+fn equipment_from_rows(rows: &[Row]) -> Equipment {
+    Equipment::new(rows.iter()
+        .filter_map(|row| Some((Slot::from_segment(row.get(0))?, row.get(1))))
+        .collect())
+}
+
+fn user_from_row(row: &Row) -> User {
+    User {
+        id: row.get(0),
+        name: row.get(1),
+        level: row.get(2),
+        xp: row.get(3),
+        attributes: Attributes { strength: row.get(4), agility: row.get(5), vitality: row.get(6) },
+        max_health: row.get(7),
+        health: row.get(8),
+        health_updated_at: row.get(9),
+        gold: row.get(10),
+        location: row.get(11),
+        last_inn: row.get(12),
+        actions: row.get(13),
+    }
+}
+
+#[test]
+fn test_retry() {
+    let mut attempts = 0;
+    let result: Result<(), Error> = retry(|| {
+        attempts += 1;
+        Err(Error::SerializationFailure)
+    });
+    assert!(matches!(result, Err(Error::SerializationFailure)));
+    assert_eq!(attempts, SERIALIZATION_ATTEMPTS);
+
+    let mut attempts = 0;
+    let result = retry(|| {
+        attempts += 1;
+        if attempts < 2 { Err(Error::SerializationFailure) } else { Ok(attempts) }
+    });
+    assert!(matches!(result, Ok(2)));
+
+    let mut attempts = 0;
+    let result: Result<(), Error> = retry(|| {
+        attempts += 1;
+        Err(Error::NotFound)
+    });
+    assert!(matches!(result, Err(Error::NotFound)));
+    assert_eq!(attempts, 1);
+}
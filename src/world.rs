@@ -0,0 +1,421 @@
+//! The map of Namushul: its locations and how they connect. Loaded from content files, see
+//! `content/world.toml`.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct World {
+    /// Where new characters begin their adventure.
+    pub start: String,
+    locations: Vec<Location>,
+    #[serde(skip)]
+    index: HashMap<String, usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub exits: Vec<Exit>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    /// Ids of the monsters that can be fought here.
+    #[serde(default)]
+    pub monsters: Vec<String>,
+    /// What may happen when entering or exploring the location.
+    #[serde(default)]
+    pub encounters: Vec<RandomEncounter>,
+}
+
+/// Something that may happen at a location: meeting a monster, finding an item, running into a
+/// wandering character, or nothing at all when none of them is set.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RandomEncounter {
+    /// How likely the encounter is, compared to the other encounters of the location.
+    pub weight: u32,
+    /// Shown when the encounter happens.
+    pub text: Option<String>,
+    /// Id of a monster to fight.
+    pub monster: Option<String>,
+    /// Id of an item to find, `quantity` times.
+    pub item: Option<String>,
+    #[serde(default = "default_quantity")]
+    pub quantity: i32,
+    /// Id of a non-player character to talk to.
+    pub npc: Option<String>,
+}
+
+fn default_quantity() -> i32 {
+    1
+}
+
+/// What happens in a [RandomEncounter].
+#[derive(Debug, PartialEq)]
+pub enum Happening<'a> {
+    Monster(&'a str),
+    /// Holds the id of the item and how many are found.
+    Item(&'a str, i32),
+    Npc(&'a str),
+    Nothing,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Exit {
+    /// Id of the location this exit leads to.
+    pub to: String,
+    /// Link text shown to the player.
+    pub label: String,
+    #[serde(default)]
+    pub cost: Cost,
+    #[serde(default)]
+    pub requires: Requirements,
+}
+
+/// What taking an exit costs the character.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cost {
+    #[serde(default)]
+    pub health: i32,
+}
+
+/// What a character needs to take an exit.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Requirements {
+    pub min_level: Option<i32>,
+    /// Id of an item the character must carry, such as a key.
+    pub item: Option<String>,
+}
+
+/// What is known about a character when deciding whether they may take an exit.
+pub struct Traveller {
+    pub level: i32,
+    pub health: i32,
+    /// Ids of the items the character carries.
+    pub items: Vec<String>,
+}
+
+/// Why a character may not take an exit.
+#[derive(Debug, PartialEq)]
+pub enum Refusal {
+    Level(i32),
+    Item(String),
+    /// Paying the health cost would knock the character out.
+    Health(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Rest,
+    Fight,
+    Explore,
+}
+
+impl Action {
+    pub fn path(self) -> &'static str {
+        match self {
+            Action::Rest => "/adventure/rest",
+            Action::Fight => "/adventure/fight",
+            Action::Explore => "/adventure/explore",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Rest => "🛏 Rest at the inn.",
+            Action::Fight => "👊 Look for something to fight.",
+            Action::Explore => "🔍 Explore the area.",
+        }
+    }
+}
+
+impl World {
+    /// Parses a world from the contents of a TOML file. Call [World::validate] before use.
+    pub fn from_toml(contents: &str) -> Result<World, toml::de::Error> {
+        let mut world: World = toml::from_str(contents)?;
+        world.index = world.locations.iter().enumerate()
+            .map(|(i, location)| (location.id.clone(), i))
+            .collect();
+        Ok(world)
+    }
+
+    pub fn location(&self, id: &str) -> Option<&Location> {
+        self.index.get(id).map(|&i| &self.locations[i])
+    }
+
+    /// Finds a location, falling back to the start for ids that are no longer part of the world,
+    /// e.g. because a character was standing in a region that has been removed since.
+    pub fn location_or_start(&self, id: &str) -> &Location {
+        self.location(id).or_else(|| self.location(&self.start))
+            .expect("a validated world has a start location")
+    }
+
+    pub fn locations(&self) -> &[Location] {
+        &self.locations
+    }
+
+    /// Lists everything wrong with the world, such as exits leading nowhere.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let mut seen = HashSet::new();
+        for location in &self.locations {
+            if location.id.is_empty() || !location.id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+                problems.push(format!("location id \"{}\" may only contain lowercase letters, digits and dashes", location.id));
+            }
+            if !seen.insert(&location.id) {
+                problems.push(format!("location {} is defined more than once", location.id));
+            }
+            if location.actions.contains(&Action::Fight) && location.monsters.is_empty() {
+                problems.push(format!("location {} offers fights, but has no monsters", location.id));
+            }
+            if location.actions.contains(&Action::Explore) && location.encounters.iter().all(|encounter| encounter.weight == 0) {
+                problems.push(format!("location {} can be explored, but has no encounters", location.id));
+            }
+            for encounter in &location.encounters {
+                let set = [&encounter.monster, &encounter.item, &encounter.npc].iter().filter(|id| id.is_some()).count();
+                if set > 1 {
+                    problems.push(format!("encounter at {} may have only one of monster, item and npc", location.id));
+                }
+                if encounter.quantity < 1 {
+                    problems.push(format!("encounter at {} must find at least one item", location.id));
+                }
+            }
+            for exit in &location.exits {
+                if self.location(&exit.to).is_none() {
+                    problems.push(format!("location {} has an exit to {}, which does not exist", location.id, exit.to));
+                }
+                if exit.cost.health < 0 {
+                    problems.push(format!("exit from {} to {} has a negative cost", location.id, exit.to));
+                }
+                if exit.requires.min_level.is_some_and(|level| level < 1) {
+                    problems.push(format!("exit from {} to {} requires a level below 1", location.id, exit.to));
+                }
+            }
+        }
+        if self.location(&self.start).is_none() {
+            problems.push(format!("start location {} does not exist", self.start));
+            return problems;
+        }
+
+        let mut reachable = HashSet::new();
+        let mut queue = VecDeque::from(vec![self.start.as_str()]);
+        while let Some(id) = queue.pop_front() {
+            if !reachable.insert(id) { continue; }
+            if let Some(location) = self.location(id) {
+                queue.extend(location.exits.iter().map(|exit| exit.to.as_str()));
+            }
+        }
+        for location in &self.locations {
+            if !reachable.contains(location.id.as_str()) {
+                problems.push(format!("location {} cannot be reached from {}", location.id, self.start));
+            }
+        }
+        problems
+    }
+}
+
+impl Location {
+    /// Whether the location has an inn, where characters rest and wake up after a knockout.
+    pub fn is_inn(&self) -> bool {
+        self.actions.contains(&Action::Rest)
+    }
+
+    pub fn exit_to(&self, id: &str) -> Option<&Exit> {
+        self.exits.iter().find(|exit| exit.to == id)
+    }
+
+    /// Picks one of the encounters, the weights deciding how likely each is. `None` when the
+    /// location has none.
+    pub fn roll_encounter(&self, rng: &mut impl Rng) -> Option<&RandomEncounter> {
+        let weights = WeightedIndex::new(self.encounters.iter().map(|encounter| encounter.weight)).ok()?;
+        Some(&self.encounters[weights.sample(rng)])
+    }
+
+    /// Whether the non-player character `npc` may be met here by chance.
+    pub fn has_wanderer(&self, npc: &str) -> bool {
+        self.encounters.iter().any(|encounter| encounter.npc.as_deref() == Some(npc))
+    }
+}
+
+impl RandomEncounter {
+    pub fn happening(&self) -> Happening<'_> {
+        match (&self.monster, &self.item, &self.npc) {
+            (Some(monster), _, _) => Happening::Monster(monster),
+            (_, Some(item), _) => Happening::Item(item, self.quantity),
+            (_, _, Some(npc)) => Happening::Npc(npc),
+            _ => Happening::Nothing,
+        }
+    }
+}
+
+impl Exit {
+    /// Checks whether `traveller` may take this exit, and pay for it.
+    pub fn check(&self, traveller: &Traveller) -> Result<(), Refusal> {
+        if let Some(level) = self.requires.min_level.filter(|&level| traveller.level < level) {
+            return Err(Refusal::Level(level));
+        }
+        if let Some(item) = self.requires.item.as_ref().filter(|item| !traveller.items.contains(item)) {
+            return Err(Refusal::Item(item.clone()));
+        }
+        if self.cost.health > 0 && traveller.health <= self.cost.health {
+            return Err(Refusal::Health(self.cost.health));
+        }
+        Ok(())
+    }
+
+    /// A short note on what the exit costs or requires, to show next to its label.
+    /// `item_name` looks up the name of a required item.
+    pub fn conditions<'a>(&'a self, item_name: impl Fn(&'a str) -> &'a str) -> Option<String> {
+        let mut conditions = vec![];
+        if let Some(level) = self.requires.min_level {
+            conditions.push(format!("level {}", level));
+        }
+        if let Some(item) = &self.requires.item {
+            conditions.push(format!("needs {}", item_name(item)));
+        }
+        if self.cost.health > 0 {
+            conditions.push(format!("costs {} HP", self.cost.health));
+        }
+        if conditions.is_empty() { None } else { Some(conditions.join(", ")) }
+    }
+}
+
+#[test]
+fn test_validate() {
+    let world = World::from_toml(r#"
+        start = "town"
+        [[locations]]
+        id = "town"
+        name = "Town"
+        description = "A town."
+        exits = [{ to = "forest", label = "To the forest" }]
+        [[locations]]
+        id = "forest"
+        name = "Forest"
+        description = "A forest."
+        actions = ["fight"]
+        monsters = ["slime"]
+        exits = [{ to = "town", label = "To town" }]
+    "#).unwrap();
+    assert!(world.validate().is_empty());
+    assert!(world.location("town").unwrap().exit_to("forest").is_some());
+    assert_eq!(world.location("forest").unwrap().actions, vec![Action::Fight]);
+    assert!(!world.location("forest").unwrap().is_inn());
+    assert_eq!(world.location_or_start("removed").id, "town");
+
+    let world = World::from_toml(r#"
+        start = "town"
+        [[locations]]
+        id = "town"
+        name = "Town"
+        description = "A town."
+        exits = [{ to = "castle", label = "To the castle" }]
+        [[locations]]
+        id = "island"
+        name = "Island"
+        description = "Nobody can get here."
+        actions = ["fight"]
+        [[locations]]
+        id = "town"
+        name = "Town again"
+        description = "A second town."
+        [[locations]]
+        id = "Bad Id"
+        name = "Bad"
+        description = "Bad."
+        exits = [{ to = "town", label = "To town" }]
+    "#).unwrap();
+    let problems = world.validate();
+    assert!(problems.iter().any(|p| p.contains("exit to castle")));
+    assert!(problems.iter().any(|p| p.contains("island cannot be reached")));
+    assert!(problems.iter().any(|p| p.contains("island offers fights, but has no monsters")));
+    assert!(problems.iter().any(|p| p.contains("town is defined more than once")));
+    assert!(problems.iter().any(|p| p.contains("\"Bad Id\" may only contain")));
+
+    assert!(World::from_toml("start = \"town\"\nlocations = []").unwrap().validate()[0].contains("start location"));
+    assert!(World::from_toml("start = \"town\"\nlocations = [{ id = \"town\", name = \"Town\", description = \"\", actions = [\"dance\"] }]").is_err());
+}
+
+#[test]
+fn test_exit_check() {
+    let world = World::from_toml(r#"
+        start = "town"
+        [[locations]]
+        id = "town"
+        name = "Town"
+        description = "A town."
+        [[locations.exits]]
+        to = "tower"
+        label = "Climb the tower"
+        cost = { health = 2 }
+        requires = { min_level = 3, item = "tower-key" }
+        [[locations]]
+        id = "tower"
+        name = "Tower"
+        description = "A tall tower."
+        exits = [{ to = "town", label = "Back down" }]
+    "#).unwrap();
+    assert!(world.validate().is_empty());
+    let exit = world.location("town").unwrap().exit_to("tower").unwrap();
+    assert_eq!(exit.conditions(|_| "the tower key").unwrap(), "level 3, needs the tower key, costs 2 HP");
+    assert_eq!(world.location("tower").unwrap().exit_to("town").unwrap().conditions(|id| id), None);
+
+    let traveller = |level, health, items: &[&str]| Traveller {
+        level,
+        health,
+        items: items.iter().map(|item| item.to_string()).collect(),
+    };
+    assert_eq!(exit.check(&traveller(2, 10, &["tower-key"])), Err(Refusal::Level(3)));
+    assert_eq!(exit.check(&traveller(3, 10, &[])), Err(Refusal::Item("tower-key".to_owned())));
+    assert_eq!(exit.check(&traveller(3, 2, &["tower-key"])), Err(Refusal::Health(2)));
+    assert_eq!(exit.check(&traveller(3, 3, &["tower-key"])), Ok(()));
+}
+
+#[test]
+fn test_roll_encounter() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let world = World::from_toml(r#"
+        start = "forest"
+        [[locations]]
+        id = "forest"
+        name = "Forest"
+        description = "A forest."
+        actions = ["explore"]
+        encounters = [
+            { weight = 0, monster = "dragon" },
+            { weight = 3, monster = "slime", text = "Something wobbles." },
+            { weight = 1, item = "herb", quantity = 2 },
+            { weight = 1 },
+        ]
+    "#).unwrap();
+    assert!(world.validate().is_empty());
+    let forest = world.location("forest").unwrap();
+    let roll = |seed| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..50).map(|_| forest.roll_encounter(&mut rng).unwrap().happening()).collect::<Vec<_>>()
+    };
+    // The same seed gives the same encounters, and encounters without weight never happen.
+    assert_eq!(roll(7), roll(7));
+    let happenings = roll(7);
+    assert!(!happenings.contains(&Happening::Monster("dragon")));
+    assert!(happenings.contains(&Happening::Monster("slime")));
+    assert!(happenings.contains(&Happening::Item("herb", 2)));
+    assert!(happenings.contains(&Happening::Nothing));
+
+    let town = World::from_toml("start = \"town\"\nlocations = [{ id = \"town\", name = \"Town\", description = \"\" }]").unwrap();
+    assert!(town.location("town").unwrap().roll_encounter(&mut StdRng::seed_from_u64(7)).is_none());
+}